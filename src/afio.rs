@@ -1,4 +1,5 @@
-use ch32v1::ch32v103::AFIO;
+use crate::rcc::Enable;
+use ch32v1::ch32v103::{self as pac, AFIO};
 use riscv::interrupt::free;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -161,3 +162,76 @@ impl AFConfig {
         })
     }
 }
+
+/// Default pin mapping (type state)
+pub struct NoRemap;
+
+/// Partial remap (type state)
+pub struct PartialRemap;
+
+/// Timer 2 partial remap 1, CH1/PA15 CH2/PB3 (type state)
+pub struct PartialRemap1;
+
+/// Timer 2 partial remap 2, CH3/PB10 CH4/PB11 (type state)
+pub struct PartialRemap2;
+
+/// Full remap (type state)
+pub struct FullRemap;
+
+/// Pin mapping of peripheral `PER` chosen at type level
+///
+/// Pin traits such as [`crate::gpio::alt::TxPin`] take the remap as a type
+/// parameter, peripheral drivers call `apply` so that AFIO matches the pins
+/// they were given.
+pub trait Remap<PER> {
+    /// Write this mapping into AFIO_PCFR, leaving the other peripherals alone
+    fn apply();
+}
+
+macro_rules! remap {
+    ($($PER:ident: $REMAP:ident => |$w:ident| $write:expr;)+) => {
+        $(
+            impl Remap<pac::$PER> for $REMAP {
+                fn apply() {
+                    let afio = unsafe { &(*(AFIO::ptr())) };
+                    AFIO::enable();
+                    free(|| afio.pcfr.modify(|_, $w| $write));
+                }
+            }
+        )+
+    };
+}
+
+remap! {
+    USART1: NoRemap => |w| w.usart1rm().bit(false);
+    USART1: FullRemap => |w| w.usart1rm().bit(true);
+    USART2: NoRemap => |w| w.usart2rm().bit(false);
+    USART3: NoRemap => |w| unsafe { w.usart3rm().bits(Usart3Remap::Default as u8) };
+    USART3: PartialRemap => |w| unsafe { w.usart3rm().bits(Usart3Remap::Partial as u8) };
+    SPI1: NoRemap => |w| w.spi1rm().bit(false);
+    SPI1: FullRemap => |w| w.spi1rm().bit(true);
+    I2C1: NoRemap => |w| w.i2c1rm().bit(false);
+    I2C1: FullRemap => |w| w.i2c1rm().bit(true);
+    TIM1: NoRemap => |w| unsafe { w.tim1rm().bits(Tim1ChRemap::Default as u8) };
+    TIM1: PartialRemap => |w| unsafe { w.tim1rm().bits(Tim1ChRemap::Partial as u8) };
+    TIM2: NoRemap => |w| w.tim2rm().bits(Tim2ChRemap::Default as u8);
+    TIM2: PartialRemap1 => |w| w.tim2rm().bits(Tim2ChRemap::Partial1 as u8);
+    TIM2: PartialRemap2 => |w| w.tim2rm().bits(Tim2ChRemap::Partial2 as u8);
+    TIM2: FullRemap => |w| w.tim2rm().bits(Tim2ChRemap::Full as u8);
+    TIM3: NoRemap => |w| unsafe { w.tim3rm().bits(Tim3ChRemap::Default as u8) };
+    TIM3: PartialRemap => |w| unsafe { w.tim3rm().bits(Tim3ChRemap::Partial as u8) };
+    TIM3: FullRemap => |w| unsafe { w.tim3rm().bits(Tim3ChRemap::Full as u8) };
+}
+
+// SPI2, I2C2 and TIM4 cannot be remapped on this package
+impl Remap<pac::SPI2> for NoRemap {
+    fn apply() {}
+}
+
+impl Remap<pac::I2C2> for NoRemap {
+    fn apply() {}
+}
+
+impl Remap<pac::TIM4> for NoRemap {
+    fn apply() {}
+}
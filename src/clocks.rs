@@ -1,28 +1,54 @@
-use ch32v1::ch32v103::{FLASH, RCC};
+use crate::gpio::{Alternate, Floating, Input, Mode, OutputSpeed, PushPull, PA8};
+use crate::rcc::Enable;
+use crate::time::Hertz;
+use ch32v1::ch32v103::{BKP, EXTEND, FLASH, PWR, RCC};
+use core::cell::Cell;
+use critical_section::Mutex;
 use riscv::interrupt::free;
 
-const MAX_CLK_FREQ: u32 = 72_000_000;
+const MAX_CLK_FREQ: Hertz = Hertz::MHz(72);
+const MAX_PCLK1_FREQ: Hertz = Hertz::MHz(36);
+const MAX_ADC_FREQ: Hertz = Hertz::MHz(14);
+const HSI_FREQ: Hertz = Hertz::MHz(8);
+const USB_FREQ: Hertz = Hertz::MHz(48);
+/// HSE range for both a crystal and an external clock on OSC_IN
+const MIN_HSE_FREQ: Hertz = Hertz::MHz(3);
+const MAX_HSE_FREQ: Hertz = Hertz::MHz(25);
+/// Highest clock the MCO pin can follow in 50 MHz output mode
+const MAX_MCO_FREQ: Hertz = Hertz::MHz(50);
+
+/// Clock values last applied by [`Clocks::setup`] or by a CSS fallback
+static FROZEN: Mutex<Cell<Option<FrozenClocks>>> = Mutex::new(Cell::new(None));
+
+/// Number of register polls before an oscillator, PLL or clock switch wait fails
+pub const RCC_TIMEOUT: u32 = 0x1_0000;
+/// Number of register polls before the LSE wait fails, the crystal needs up to seconds
+pub const LSE_TIMEOUT: u32 = 0x100_0000;
+
+/// Nominal LSE crystal frequency
+pub const LSE_FREQ: Hertz = Hertz::Hz(32_768);
+/// Nominal LSI frequency, the actual value varies between 30 and 60 kHz
+pub const LSI_FREQ: Hertz = Hertz::kHz(40);
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PllSrc {
     Hsi,
-    Hse(u32),
-    HseDiv2(u32),
+    /// HSI divided by 2 through EXTEND_CTR.HSIPRE
+    HsiDiv2,
+    Hse(Hertz),
+    HseDiv2(Hertz),
 }
 
 impl PllSrc {
     pub fn bits(&self) -> bool {
-        match self {
-            Self::Hsi => false,
-            _ => true,
-        }
+        !matches!(self, Self::Hsi | Self::HsiDiv2)
     }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum InputSrc {
     Hsi,
-    Hse(u8),
+    Hse(Hertz),
     Pll(PllSrc),
     Unavailable,
 }
@@ -76,6 +102,22 @@ pub enum AHBPreDiv {
     Div512 = 0b1111,
 }
 
+impl AHBPreDiv {
+    pub fn div(&self) -> u32 {
+        match self {
+            Self::NoDiv => 1,
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div8 => 8,
+            Self::Div16 => 16,
+            Self::Div64 => 64,
+            Self::Div128 => 128,
+            Self::Div256 => 256,
+            Self::Div512 => 512,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum APB1PreDiv {
     NoDiv = 0b000,
@@ -85,6 +127,18 @@ pub enum APB1PreDiv {
     Div16 = 0b111,
 }
 
+impl APB1PreDiv {
+    pub fn div(&self) -> u32 {
+        match self {
+            Self::NoDiv => 1,
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div8 => 8,
+            Self::Div16 => 16,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum APB2PreDiv {
     NoDiv = 0b000,
@@ -94,6 +148,18 @@ pub enum APB2PreDiv {
     Div16 = 0b111,
 }
 
+impl APB2PreDiv {
+    pub fn div(&self) -> u32 {
+        match self {
+            Self::NoDiv => 1,
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div8 => 8,
+            Self::Div16 => 16,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ADCPreDiv {
     Div2 = 0b00,
@@ -102,16 +168,129 @@ pub enum ADCPreDiv {
     Div8 = 0b11,
 }
 
+impl ADCPreDiv {
+    pub fn div(&self) -> u32 {
+        match self {
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div6 => 6,
+            Self::Div8 => 8,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum USBPreDiv {
     NoDiv = 0b01,
     Div1_5 = 0b00,
 }
 
+impl USBPreDiv {
+    pub fn bit(&self) -> bool {
+        match self {
+            Self::NoDiv => true,
+            Self::Div1_5 => false,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RccError {
     Speed,
     Hardware,
+    /// HSE frequency was not given or is outside 3..=25 MHz
+    HseFreq,
+    /// No PLL source and multiplier produce the requested SYSCLK
+    NoPllSolution,
+    /// The PLL output cannot be divided down to 48 MHz for USB
+    NoUsbSolution,
+    /// No AHB prescaler produces the requested HCLK
+    NoHclkSolution,
+    /// No APB1 prescaler produces the requested PCLK1
+    NoPclk1Solution,
+    /// No APB2 prescaler produces the requested PCLK2
+    NoPclk2Solution,
+    /// No ADC prescaler produces the requested ADC clock
+    NoAdcSolution,
+    /// The configuration cannot be applied (e.g. unavailable clock source)
+    InvalidConfig,
+    /// HSE did not become ready in time
+    HseTimeout,
+    /// HSI did not become ready in time
+    HsiTimeout,
+    /// The PLL did not lock (or unlock) in time
+    PllLockTimeout,
+    /// SWS did not report the requested SYSCLK source in time
+    SwitchTimeout,
+    /// LSE did not become ready in time
+    LseTimeout,
+    /// LSI did not become ready in time
+    LsiTimeout,
+}
+
+/// Clock frequencies that were applied by [`Clocks::setup`]
+///
+/// Peripheral drivers take this by reference, so dividers and baud rates are
+/// always derived from the configuration the hardware actually runs with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrozenClocks {
+    sysclk: Hertz,
+    hclk: Hertz,
+    pclk1: Hertz,
+    pclk2: Hertz,
+    pclk1_tim: Hertz,
+    pclk2_tim: Hertz,
+    adcclk: Hertz,
+    usbclk: Hertz,
+    hse: Hertz,
+    pllclk: Hertz,
+}
+
+impl FrozenClocks {
+    pub fn sysclk(&self) -> Hertz {
+        self.sysclk
+    }
+
+    pub fn hclk(&self) -> Hertz {
+        self.hclk
+    }
+
+    pub fn pclk1(&self) -> Hertz {
+        self.pclk1
+    }
+
+    pub fn pclk2(&self) -> Hertz {
+        self.pclk2
+    }
+
+    /// Clock of the timers on APB1 (TIM2..TIM4)
+    pub fn pclk1_tim(&self) -> Hertz {
+        self.pclk1_tim
+    }
+
+    /// Clock of the timers on APB2 (TIM1)
+    pub fn pclk2_tim(&self) -> Hertz {
+        self.pclk2_tim
+    }
+
+    pub fn adcclk(&self) -> Hertz {
+        self.adcclk
+    }
+
+    /// USB clock, zero when the PLL is not running
+    pub fn usbclk(&self) -> Hertz {
+        self.usbclk
+    }
+
+    /// HSE frequency, zero when HSE is not in use
+    pub fn hse(&self) -> Hertz {
+        self.hse
+    }
+
+    /// PLL output clock, zero when the PLL is not running
+    pub fn pllclk(&self) -> Hertz {
+        self.pllclk
+    }
 }
 
 pub struct Clocks {
@@ -122,129 +301,368 @@ pub struct Clocks {
     pub apb1_prediv: APB1PreDiv,
     pub apb2_prediv: APB2PreDiv,
     pub adc_prediv: ADCPreDiv,
+    pub usb_prediv: USBPreDiv,
     pub css: bool,
     pub hse_bypass: bool,
 }
 
 impl Clocks {
-    pub fn setup(&self) -> Result<(), RccError> {
-        // if let Err(e) = self.validate_freq() {
-        //     return Err(e);
-        // }
+    /// Create a builder that derives the clock tree from target frequencies
+    pub fn builder() -> ClocksBuilder {
+        ClocksBuilder::default()
+    }
 
-        let rcc = unsafe { &(*(RCC::ptr())) };
+    /// Apply the configuration to the RCC and return the frozen clock values
+    ///
+    /// The configuration is validated first, and every oscillator, PLL and
+    /// clock switch wait is bounded by [`RCC_TIMEOUT`] polls. When HSE fails to
+    /// start, SYSCLK is left on its current source. A PLL that drives SYSCLK is
+    /// only reprogrammed after SYSCLK has been moved to HSI, so if it then fails
+    /// to lock SYSCLK stays on HSI and [`frozen`] reports that state.
+    pub fn setup(&self, _rcc: RCC) -> Result<FrozenClocks, RccError> {
+        self.apply()
+    }
 
-        let flash = unsafe { &(*(FLASH::ptr())) };
+    /// Switch to this configuration at runtime and notify `listeners`
+    ///
+    /// Flash wait states are raised before speeding up and lowered after
+    /// slowing down. If the PLL drives SYSCLK it is only reprogrammed after
+    /// SYSCLK has been moved to HSI, and it is stopped once no longer used.
+    ///
+    /// If the clocks changed before an error, e.g. SYSCLK was left on HSI by a
+    /// PLL that failed to lock, `listeners` are notified of the clocks that
+    /// actually run before the error is returned.
+    pub fn reconfigure(
+        &self,
+        listeners: &mut [&mut dyn ClockListener],
+    ) -> Result<FrozenClocks, RccError> {
+        let before = frozen();
+        let result = self.apply();
 
-        flash.actlr.modify(|_, w| {
-            w.prftbe().bit(true);
-            if self.sysclk() < 48_000_000 {
-                w.latency().one()
-            } else {
-                w.latency().two()
+        let changed = match result {
+            Ok(clocks) => Some(clocks),
+            Err(_) => frozen().filter(|clocks| Some(*clocks) != before),
+        };
+        if let Some(clocks) = changed {
+            for listener in listeners.iter_mut() {
+                listener.clocks_changed(&clocks);
             }
-        });
+        }
 
-        // let sysclk = self.sysclk();
+        result
+    }
+
+    fn apply(&self) -> Result<FrozenClocks, RccError> {
+        self.validate_freq()?;
+
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        let flash = unsafe { &(*(FLASH::ptr())) };
 
-        free(|| unsafe {
-            match self.input_src {
-                InputSrc::Hsi => {
-                    rcc.ctlr.modify(|_, w| w.hsion().on());
-                    while rcc.ctlr.read().hsirdy().is_not_ready() {}
+        let latency = flash_latency(self.sysclk());
+        let raise_latency = latency > flash.actlr.read().latency().bits();
+        // before the first setup, compare with whatever the bootloader left running
+        let running = frozen().or_else(|| Self::current(self.hse()).ok());
+        let speeding_up = self.sysclk() > running.map_or(HSI_FREQ, |c| c.sysclk());
+
+        if raise_latency {
+            flash.actlr.modify(|_, w| unsafe { w.prftbe().bit(true).latency().bits(latency) });
+        }
+
+        let mut on_hsi = false;
+
+        let result = free(|| unsafe {
+            let use_hse = matches!(
+                self.input_src,
+                InputSrc::Hse(_) | InputSrc::Pll(PllSrc::Hse(_)) | InputSrc::Pll(PllSrc::HseDiv2(_))
+            );
+
+            if use_hse {
+                // bypass can only be changed while HSE is off
+                if rcc.ctlr.read().hserdy().is_not_ready() {
+                    rcc.ctlr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
                 }
-                InputSrc::Hse(_) => {
-                    rcc.ctlr.modify(|_, w| w.hseon().on());
-                    while rcc.ctlr.read().hserdy().is_not_ready() {}
+                rcc.ctlr.modify(|_, w| w.hseon().on());
+                if let Err(e) = wait_for(|| rcc.ctlr.read().hserdy().is_ready(), RccError::HseTimeout) {
+                    rcc.ctlr.modify(|_, w| w.hseon().off());
+                    return Err(e);
                 }
-                InputSrc::Pll(src) => match src {
-                    PllSrc::Hse(_) => {
-                        rcc.ctlr.modify(|_, w| w.hseon().bit(true));
-                        while rcc.ctlr.read().hserdy().is_not_ready() {}
-                    }
-                    PllSrc::HseDiv2(_) => {
-                        rcc.ctlr.modify(|_, w| w.hseon().on());
-                        while rcc.ctlr.read().hserdy().is_not_ready() {}
-                    }
-                    _ => {
-                        rcc.ctlr.modify(|_, w| w.hsion().on());
-                        while rcc.ctlr.read().hsirdy().is_not_ready() {}
-                    }
-                },
-                InputSrc::Unavailable => {}
             }
 
-            rcc.ctlr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
+            if !use_hse || rcc.cfgr0.read().sws().is_pll() {
+                rcc.ctlr.modify(|_, w| w.hsion().on());
+                wait_for(|| rcc.ctlr.read().hsirdy().is_ready(), RccError::HsiTimeout)?;
+            }
 
             if let InputSrc::Pll(src) = self.input_src {
+                // the PLL cannot be reprogrammed while it drives SYSCLK
+                if rcc.cfgr0.read().sws().is_pll() {
+                    rcc.cfgr0.modify(|_, w| w.sw().hsi());
+                    wait_for(|| rcc.cfgr0.read().sws().is_hsi(), RccError::SwitchTimeout)?;
+                    on_hsi = true;
+                }
+
                 rcc.ctlr.modify(|_, w| w.pllon().bit(false));
 
-                while rcc.ctlr.read().pllrdy().is_ready() {}
+                wait_for(|| rcc.ctlr.read().pllrdy().is_not_ready(), RccError::PllLockTimeout)?;
 
-                rcc.cfgr0
-                    .modify(|_, w| w.pllsrc().bit(src.bits()).pllmul().bits(self.pllmul as u8));
+                let extend = &(*EXTEND::ptr());
+                match src {
+                    PllSrc::Hsi => extend.extend_ctr.modify(|_, w| w.hsipre().no_div()),
+                    PllSrc::HsiDiv2 => extend.extend_ctr.modify(|_, w| w.hsipre().div2()),
+                    _ => {}
+                }
+
+                rcc.cfgr0.modify(|_, w| {
+                    w.pllsrc()
+                        .bit(src.bits())
+                        .pllxtpre()
+                        .bit(matches!(src, PllSrc::HseDiv2(_)))
+                        .pllmul()
+                        .bits(self.pllmul as u8)
+                });
 
                 rcc.ctlr.modify(|_, w| w.pllon().bit(true));
 
-                while rcc.ctlr.read().pllrdy().is_not_ready() {}
+                if let Err(e) = wait_for(|| rcc.ctlr.read().pllrdy().is_ready(), RccError::PllLockTimeout) {
+                    rcc.ctlr.modify(|_, w| w.pllon().bit(false));
+                    return Err(e);
+                }
             }
 
-            rcc.cfgr0.modify(|_, w| {
-                w.sw().bits(self.input_src.bits());
-                w.hpre().bits(self.ahb_prediv as u8);
-                w.ppre1().bits(self.apb1_prediv as u8);
-                w.ppre2().bits(self.apb2_prediv as u8)
-            });
+            let prescalers = || {
+                rcc.cfgr0.modify(|_, w| {
+                    w.hpre().bits(self.ahb_prediv as u8);
+                    w.ppre1().bits(self.apb1_prediv as u8);
+                    w.ppre2().bits(self.apb2_prediv as u8);
+                    w.adcpre().bits(self.adc_prediv as u8);
+                    w.usbpre().bit(self.usb_prediv.bit())
+                })
+            };
+
+            // keep the buses within their limits while SYSCLK changes
+            if speeding_up {
+                prescalers();
+            }
+
+            rcc.cfgr0.modify(|_, w| w.sw().bits(self.input_src.bits()));
+
+            wait_for(
+                || rcc.cfgr0.read().sws().bits() == self.input_src.bits(),
+                RccError::SwitchTimeout,
+            )?;
+
+            if !speeding_up {
+                prescalers();
+            }
+
+            // only stop the PLL now that it no longer drives SYSCLK
+            if !matches!(self.input_src, InputSrc::Pll(_)) {
+                rcc.ctlr.modify(|_, w| w.pllon().bit(false));
+            }
 
             rcc.ctlr.modify(|_, w| w.csson().bit(self.css));
+
+            Ok(())
         });
-        Ok(())
+
+        if let Err(e) = result {
+            // SYSCLK no longer runs from the frozen source, publish what does
+            if on_hsi {
+                if let Ok(running) = Self::current(self.hse()) {
+                    critical_section::with(|cs| FROZEN.borrow(cs).set(Some(running)));
+                }
+            }
+            return Err(e);
+        }
+
+        if !raise_latency {
+            flash.actlr.modify(|_, w| unsafe { w.prftbe().bit(true).latency().bits(latency) });
+        }
+
+        let frozen = self.freeze();
+        critical_section::with(|cs| FROZEN.borrow(cs).set(Some(frozen)));
+
+        Ok(frozen)
+    }
+
+    /// HSE frequency of the configuration, if it uses HSE
+    fn hse(&self) -> Option<Hertz> {
+        match self.input_src {
+            InputSrc::Hse(f) | InputSrc::Pll(PllSrc::Hse(f)) | InputSrc::Pll(PllSrc::HseDiv2(f)) => Some(f),
+            _ => None,
+        }
+    }
+
+    fn freeze(&self) -> FrozenClocks {
+        let pclk1 = self.pclk1();
+        let pclk2 = self.pclk2();
+
+        FrozenClocks {
+            sysclk: self.sysclk(),
+            hclk: self.hclk(),
+            pclk1,
+            pclk2,
+            pclk1_tim: match self.apb1_prediv {
+                APB1PreDiv::NoDiv => pclk1,
+                _ => pclk1 * 2,
+            },
+            pclk2_tim: match self.apb2_prediv {
+                APB2PreDiv::NoDiv => pclk2,
+                _ => pclk2 * 2,
+            },
+            adcclk: self.adc_clk(),
+            usbclk: self.usb_clk(),
+            hse: match self.input_src {
+                InputSrc::Hse(f) | InputSrc::Pll(PllSrc::Hse(f)) | InputSrc::Pll(PllSrc::HseDiv2(f)) => f,
+                _ => Hertz::from_raw(0),
+            },
+            pllclk: match self.input_src {
+                InputSrc::Pll(_) => self.sysclk(),
+                _ => Hertz::from_raw(0),
+            },
+        }
     }
 
-    pub fn sysclk(&self) -> u32 {
+    pub fn sysclk(&self) -> Hertz {
         match self.input_src {
-            InputSrc::Hsi => 8_000_000,
-            InputSrc::Hse(f) => f as u32 * 1_000_000,
+            InputSrc::Hsi => HSI_FREQ,
+            InputSrc::Hse(f) => f,
             InputSrc::Pll(src) => match src {
-                PllSrc::Hse(v) => v * self.pllmul.val() * 1_000_000,
-                PllSrc::HseDiv2(v) => v / 2 * self.pllmul.val() * 1_000_000,
-                PllSrc::Hsi => 8_000_000 * self.pllmul.val(),
+                PllSrc::Hse(f) => f * self.pllmul.val(),
+                PllSrc::HseDiv2(f) => f / 2 * self.pllmul.val(),
+                PllSrc::Hsi => HSI_FREQ * self.pllmul.val(),
+                PllSrc::HsiDiv2 => HSI_FREQ / 2 * self.pllmul.val(),
             },
-            InputSrc::Unavailable => 0,
+            InputSrc::Unavailable => Hertz::from_raw(0),
         }
     }
 
-    pub fn hclk(&self) -> u32 {
-        self.sysclk() / self.ahb_prediv as u32
+    pub fn hclk(&self) -> Hertz {
+        self.sysclk() / self.ahb_prediv.div()
     }
 
-    pub fn pclk1(&self) -> u32 {
-        self.hclk() / self.apb1_prediv as u32
+    pub fn pclk1(&self) -> Hertz {
+        self.hclk() / self.apb1_prediv.div()
     }
 
-    pub fn pclk2(&self) -> u32 {
-        self.hclk() / self.apb2_prediv as u32
+    pub fn pclk2(&self) -> Hertz {
+        self.hclk() / self.apb2_prediv.div()
     }
 
-    pub fn adc_clk(&self) -> u32 {
-        self.pclk2() / self.adc_prediv as u32
+    pub fn adc_clk(&self) -> Hertz {
+        self.pclk2() / self.adc_prediv.div()
     }
 
-    pub fn input_src(&self) -> InputSrc {
+    pub fn usb_clk(&self) -> Hertz {
+        match self.input_src {
+            InputSrc::Pll(_) => match self.usb_prediv {
+                USBPreDiv::NoDiv => self.sysclk(),
+                USBPreDiv::Div1_5 => self.sysclk() * 2 / 3,
+            },
+            _ => Hertz::from_raw(0),
+        }
+    }
+
+    /// Decode the clock tree that is currently running from the RCC registers
+    ///
+    /// HSE frequency cannot be read back, so `hse` must give it whenever HSE
+    /// drives SYSCLK or the PLL.
+    pub fn from_hardware(hse: Option<Hertz>) -> Result<Self, RccError> {
         let rcc = unsafe { &(*(RCC::ptr())) };
+
         free(|| {
-            let bits = rcc.cfgr0.read().sws().bits();
-            if bits == 0b00 {
-                InputSrc::Hsi
-            } else if bits == 0b01 || bits == 0b10 {
-                self.input_src
+            let ctlr = rcc.ctlr.read();
+            let cfgr0 = rcc.cfgr0.read();
+
+            let input_src = match cfgr0.sws().bits() {
+                0b00 => InputSrc::Hsi,
+                0b01 => InputSrc::Hse(hse_freq(hse)?),
+                0b10 => InputSrc::Pll(pll_src(hse)?),
+                _ => InputSrc::Unavailable,
+            };
+
+            let pllmul = pll_mul(cfgr0.pllmul().bits());
+
+            let ahb_prediv = match cfgr0.hpre().bits() {
+                0b1000 => AHBPreDiv::Div2,
+                0b1001 => AHBPreDiv::Div4,
+                0b1010 => AHBPreDiv::Div8,
+                0b1011 => AHBPreDiv::Div16,
+                0b1100 => AHBPreDiv::Div64,
+                0b1101 => AHBPreDiv::Div128,
+                0b1110 => AHBPreDiv::Div256,
+                0b1111 => AHBPreDiv::Div512,
+                _ => AHBPreDiv::NoDiv,
+            };
+
+            let apb1_prediv = match cfgr0.ppre1().bits() {
+                0b100 => APB1PreDiv::Div2,
+                0b101 => APB1PreDiv::Div4,
+                0b110 => APB1PreDiv::Div8,
+                0b111 => APB1PreDiv::Div16,
+                _ => APB1PreDiv::NoDiv,
+            };
+
+            let apb2_prediv = match cfgr0.ppre2().bits() {
+                0b100 => APB2PreDiv::Div2,
+                0b101 => APB2PreDiv::Div4,
+                0b110 => APB2PreDiv::Div8,
+                0b111 => APB2PreDiv::Div16,
+                _ => APB2PreDiv::NoDiv,
+            };
+
+            let adc_prediv = match cfgr0.adcpre().bits() {
+                0b00 => ADCPreDiv::Div2,
+                0b01 => ADCPreDiv::Div4,
+                0b10 => ADCPreDiv::Div6,
+                _ => ADCPreDiv::Div8,
+            };
+
+            let usb_prediv = if cfgr0.usbpre().bit() {
+                USBPreDiv::NoDiv
             } else {
-                InputSrc::Unavailable
-            }
+                USBPreDiv::Div1_5
+            };
+
+            Ok(Self {
+                input_src,
+                sysclk_state: input_src,
+                pllmul,
+                ahb_prediv,
+                apb1_prediv,
+                apb2_prediv,
+                adc_prediv,
+                usb_prediv,
+                css: ctlr.csson().bit(),
+                hse_bypass: ctlr.hsebyp().bit(),
+            })
         })
     }
 
+    /// Frequencies of the clock tree that is currently running
+    pub fn current(hse: Option<Hertz>) -> Result<FrozenClocks, RccError> {
+        Ok(Self::from_hardware(hse)?.freeze())
+    }
+
+    /// Source SYSCLK currently runs from, decoded by [`Clocks::from_hardware`]
+    ///
+    /// The HSE frequency is taken from this configuration, `Unavailable` is
+    /// returned when HSE drives SYSCLK but this configuration does not use it.
+    pub fn input_src(&self) -> InputSrc {
+        Self::from_hardware(self.hse()).map_or(InputSrc::Unavailable, |clocks| clocks.input_src)
+    }
+
     pub fn validate_freq(&self) -> Result<(), RccError> {
+        match self.input_src {
+            InputSrc::Unavailable => return Err(RccError::InvalidConfig),
+            InputSrc::Hse(f) | InputSrc::Pll(PllSrc::Hse(f)) | InputSrc::Pll(PllSrc::HseDiv2(f))
+                if !hse_in_range(f) =>
+            {
+                return Err(RccError::InvalidConfig)
+            }
+            _ => {}
+        }
+
         if self.sysclk() > MAX_CLK_FREQ {
             return Err(RccError::Speed);
         }
@@ -253,7 +671,7 @@ impl Clocks {
             return Err(RccError::Speed);
         }
 
-        if self.pclk1() > MAX_CLK_FREQ {
+        if self.pclk1() > MAX_PCLK1_FREQ {
             return Err(RccError::Speed);
         }
 
@@ -261,7 +679,7 @@ impl Clocks {
             return Err(RccError::Speed);
         }
 
-        if self.adc_clk() > 14_000_000 {
+        if self.adc_clk() > MAX_ADC_FREQ {
             return Err(RccError::Speed);
         }
 
@@ -279,8 +697,730 @@ impl Default for Clocks {
             apb1_prediv: APB1PreDiv::NoDiv,
             apb2_prediv: APB2PreDiv::NoDiv,
             adc_prediv: ADCPreDiv::Div2,
+            usb_prediv: USBPreDiv::Div1_5,
             css: false,
             hse_bypass: false,
         }
     }
 }
+
+/// Whether the chip accepts `hse` as HSE frequency
+fn hse_in_range(hse: Hertz) -> bool {
+    (MIN_HSE_FREQ..=MAX_HSE_FREQ).contains(&hse)
+}
+
+/// HSE frequency given to a register decoder, needed once HSE is in use
+fn hse_freq(hse: Option<Hertz>) -> Result<Hertz, RccError> {
+    match hse {
+        Some(f) if f.raw() != 0 => Ok(f),
+        _ => Err(RccError::HseFreq),
+    }
+}
+
+/// PLL multiplier selected by the CFGR0 PLLMUL field `bits`
+fn pll_mul(bits: u8) -> PllMul {
+    match bits {
+        0b0000 => PllMul::Mul2,
+        0b0001 => PllMul::Mul3,
+        0b0010 => PllMul::Mul4,
+        0b0011 => PllMul::Mul5,
+        0b0100 => PllMul::Mul6,
+        0b0101 => PllMul::Mul7,
+        0b0110 => PllMul::Mul8,
+        0b0111 => PllMul::Mul9,
+        0b1000 => PllMul::Mul10,
+        0b1001 => PllMul::Mul11,
+        0b1010 => PllMul::Mul12,
+        0b1011 => PllMul::Mul13,
+        0b1100 => PllMul::Mul14,
+        0b1101 => PllMul::Mul15,
+        _ => PllMul::Mul16,
+    }
+}
+
+/// PLL input selected in CFGR0 and EXTEND_CTR, `hse` is needed when it is HSE
+fn pll_src(hse: Option<Hertz>) -> Result<PllSrc, RccError> {
+    let rcc = unsafe { &(*(RCC::ptr())) };
+    let extend = unsafe { &(*(EXTEND::ptr())) };
+
+    let cfgr0 = rcc.cfgr0.read();
+    Ok(if cfgr0.pllsrc().is_hsi() {
+        if extend.extend_ctr.read().hsipre().is_no_div() {
+            PllSrc::Hsi
+        } else {
+            PllSrc::HsiDiv2
+        }
+    } else if cfgr0.pllxtpre().is_div2() {
+        PllSrc::HseDiv2(hse_freq(hse)?)
+    } else {
+        PllSrc::Hse(hse_freq(hse)?)
+    })
+}
+
+/// Output of the PLL while it is locked, also when it does not drive SYSCLK
+fn pll_output(hse: Option<Hertz>) -> Option<Hertz> {
+    let rcc = unsafe { &(*(RCC::ptr())) };
+
+    if rcc.ctlr.read().pllrdy().is_not_ready() {
+        return None;
+    }
+
+    let clocks = Clocks {
+        input_src: InputSrc::Pll(pll_src(hse).ok()?),
+        pllmul: pll_mul(rcc.cfgr0.read().pllmul().bits()),
+        ..Clocks::default()
+    };
+    Some(clocks.sysclk())
+}
+
+/// Flash wait states recommended for `sysclk`
+fn flash_latency(sysclk: Hertz) -> u8 {
+    if sysclk <= Hertz::MHz(24) {
+        0
+    } else if sysclk <= Hertz::MHz(48) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Driver that derives dividers from the clock tree
+///
+/// Pass listeners to [`Clocks::reconfigure`] so they can recompute baud rates,
+/// delays and timer periods after a runtime clock change.
+pub trait ClockListener {
+    fn clocks_changed(&mut self, clocks: &FrozenClocks);
+}
+
+/// Clock values currently in effect
+///
+/// Returns the values applied by the last [`Clocks::setup`], updated after a
+/// clock security system fallback to HSI. `None` before the first setup.
+pub fn frozen() -> Option<FrozenClocks> {
+    critical_section::with(|cs| FROZEN.borrow(cs).get())
+}
+
+/// Clock security system
+///
+/// When HSE fails while CSS is enabled, the hardware raises an NMI. With the
+/// `css-handler` feature this module defines the `NMI` vector itself,
+/// otherwise call [`Css::on_nmi`] from your own to recover:
+///
+/// ```ignore
+/// #[no_mangle]
+/// extern "C" fn NMI() {
+///     Css::on_nmi();
+/// }
+/// ```
+pub struct Css;
+
+/// Callback invoked with the new clock values after a CSS fallback
+pub type CssCallback = fn(&FrozenClocks);
+
+static CSS_CALLBACK: Mutex<Cell<Option<CssCallback>>> = Mutex::new(Cell::new(None));
+static CSS_TRIGGERED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+impl Css {
+    /// Turn on HSE monitoring, `callback` runs after a fallback to HSI
+    pub fn enable(callback: Option<CssCallback>) {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        critical_section::with(|cs| CSS_CALLBACK.borrow(cs).set(callback));
+        free(|| rcc.ctlr.modify(|_, w| w.csson().bit(true)));
+    }
+
+    pub fn disable() {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        free(|| rcc.ctlr.modify(|_, w| w.csson().bit(false)));
+        critical_section::with(|cs| CSS_CALLBACK.borrow(cs).set(None));
+    }
+
+    /// Whether an HSE failure has been handled since the last [`Css::clear`]
+    pub fn triggered() -> bool {
+        critical_section::with(|cs| CSS_TRIGGERED.borrow(cs).get())
+    }
+
+    pub fn clear() {
+        critical_section::with(|cs| CSS_TRIGGERED.borrow(cs).set(false));
+    }
+
+    /// Handle a CSS event, to be called from the `NMI` handler
+    ///
+    /// Clears the CSS flag, makes sure SYSCLK runs from HSI with HSE and the
+    /// PLL stopped, refreshes [`frozen`] and invokes the registered callback.
+    /// Returns `false` if the NMI was not raised by the CSS.
+    pub fn on_nmi() -> bool {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        if !rcc.intr.read().cssf().bit() {
+            return false;
+        }
+
+        rcc.intr.modify(|_, w| w.cssc().clear_bit_by_one());
+
+        free(|| {
+            rcc.ctlr.modify(|_, w| w.hsion().on());
+            let _ = wait_for(|| rcc.ctlr.read().hsirdy().is_ready(), RccError::HsiTimeout);
+            rcc.cfgr0.modify(|_, w| w.sw().hsi());
+            let _ = wait_for(|| rcc.cfgr0.read().sws().is_hsi(), RccError::SwitchTimeout);
+            rcc.ctlr.modify(|_, w| w.pllon().bit(false).hseon().off());
+        });
+
+        let frozen = Clocks::from_hardware(None).map(|c| c.freeze()).ok();
+
+        let callback = critical_section::with(|cs| {
+            FROZEN.borrow(cs).set(frozen);
+            CSS_TRIGGERED.borrow(cs).set(true);
+            CSS_CALLBACK.borrow(cs).get()
+        });
+
+        if let (Some(callback), Some(frozen)) = (callback, frozen) {
+            callback(&frozen);
+        }
+
+        true
+    }
+}
+
+#[cfg(feature = "css-handler")]
+mod handlers {
+    use super::Css;
+
+    #[no_mangle]
+    extern "C" fn NMI() {
+        Css::on_nmi();
+    }
+}
+
+/// Way the LSE oscillator is driven
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LseMode {
+    /// 32.768 kHz crystal on OSC32_IN/OSC32_OUT
+    Crystal,
+    /// External clock signal on OSC32_IN
+    Bypass,
+}
+
+/// Clock source of the RTC, selected in BDCTLR.RTCSEL
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RtcSrc {
+    Lse,
+    Lsi,
+    /// HSE divided by 128
+    HseDiv128,
+}
+
+/// Low speed external oscillator, lives in the backup domain
+///
+/// CH32V103 has no LSE drive strength setting, only crystal or bypass.
+pub struct Lse;
+
+impl Lse {
+    /// Start the LSE and wait for it to become ready, returns its frequency
+    pub fn enable(mode: LseMode) -> Result<Hertz, RccError> {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        unlock_backup_domain();
+
+        // only the BDCTLR writes are masked, the waits may take seconds
+        free(|| rcc.bdctlr.modify(|_, w| w.lseon().off()));
+        // bypass can only be changed while LSE is off
+        wait_for(|| !rcc.bdctlr.read().lserdy().bit(), RccError::LseTimeout)?;
+        free(|| {
+            rcc.bdctlr.modify(|_, w| match mode {
+                LseMode::Crystal => w.lsebyp().not_bypassed(),
+                LseMode::Bypass => w.lsebyp().bypassed(),
+            });
+            rcc.bdctlr.modify(|_, w| w.lseon().on());
+        });
+        wait_for_polls(LSE_TIMEOUT, || rcc.bdctlr.read().lserdy().bit(), RccError::LseTimeout)?;
+
+        Ok(LSE_FREQ)
+    }
+
+    pub fn disable() {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        unlock_backup_domain();
+
+        free(|| rcc.bdctlr.modify(|_, w| w.lseon().off()));
+    }
+
+    pub fn is_ready() -> bool {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        rcc.bdctlr.read().lserdy().bit()
+    }
+}
+
+/// Low speed internal RC oscillator, clocks the independent watchdog
+pub struct Lsi;
+
+impl Lsi {
+    /// Start the LSI and wait for it to become ready, returns its nominal frequency
+    pub fn enable() -> Result<Hertz, RccError> {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        free(|| rcc.rstsckr.modify(|_, w| w.lsion().on()));
+        wait_for(|| rcc.rstsckr.read().lsirdy().bit(), RccError::LsiTimeout)?;
+
+        Ok(LSI_FREQ)
+    }
+
+    /// Stop the LSI, this has no effect while the independent watchdog runs
+    pub fn disable() {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        free(|| rcc.rstsckr.modify(|_, w| w.lsion().off()));
+    }
+
+    pub fn is_ready() -> bool {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+        rcc.rstsckr.read().lsirdy().bit()
+    }
+}
+
+/// Select the RTC clock and enable it
+///
+/// The source must already run, see [`Lse::enable`] and [`Lsi::enable`],
+/// otherwise the RTC is left alone and the timeout error of that oscillator
+/// is returned.
+///
+/// RTCSEL can only be written once after a backup domain reset, pass
+/// `reset_backup_domain` to change an already selected source. This also
+/// clears the backup registers and stops the LSE, which is then restarted in
+/// the mode it ran in.
+pub fn set_rtc_clock(src: RtcSrc, reset_backup_domain: bool) -> Result<(), RccError> {
+    let rcc = unsafe { &(*(RCC::ptr())) };
+
+    match src {
+        RtcSrc::Lse if !Lse::is_ready() => return Err(RccError::LseTimeout),
+        RtcSrc::Lsi if !Lsi::is_ready() => return Err(RccError::LsiTimeout),
+        RtcSrc::HseDiv128 if rcc.ctlr.read().hserdy().is_not_ready() => return Err(RccError::HseTimeout),
+        _ => {}
+    }
+
+    unlock_backup_domain();
+
+    if reset_backup_domain {
+        let bdctlr = rcc.bdctlr.read();
+        let lse = bdctlr.lseon().bit().then_some(if bdctlr.lsebyp().bit() {
+            LseMode::Bypass
+        } else {
+            LseMode::Crystal
+        });
+
+        free(|| {
+            rcc.bdctlr.modify(|_, w| w.bdrst().reset());
+            rcc.bdctlr.modify(|_, w| w.bdrst().cancel());
+        });
+
+        if let Some(mode) = lse {
+            Lse::enable(mode)?;
+        }
+    }
+
+    free(|| {
+        rcc.bdctlr.modify(|_, w| {
+            match src {
+                RtcSrc::Lse => w.rtcsel().lse(),
+                RtcSrc::Lsi => w.rtcsel().lsi(),
+                RtcSrc::HseDiv128 => w.rtcsel().hse(),
+            };
+            w.rtcen().enabled()
+        });
+    });
+
+    Ok(())
+}
+
+/// Enable write access to BDCTLR and the backup registers
+fn unlock_backup_domain() {
+    let pwr = unsafe { &(*(PWR::ptr())) };
+
+    PWR::enable();
+    BKP::enable();
+    free(|| pwr.ctlr.modify(|_, w| w.dbp().set_bit()));
+}
+
+/// Clock routed to the MCO pin
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum McoSrc {
+    Sysclk = 0b100,
+    Hsi = 0b101,
+    Hse = 0b110,
+    PllDiv2 = 0b111,
+}
+
+/// Microcontroller clock output on PA8
+pub struct Mco {
+    pin: PA8<Alternate<PushPull>>,
+}
+
+impl Mco {
+    /// Route `src` to PA8, configured as 50 MHz alternate push-pull
+    ///
+    /// Fails if `src` is not running or if it is faster than the pin can drive.
+    pub fn new<MODE: Mode>(
+        pin: PA8<MODE>,
+        src: McoSrc,
+        clocks: &FrozenClocks,
+    ) -> Result<Self, (PA8<MODE>, RccError)> {
+        let freq = match src {
+            McoSrc::Sysclk => clocks.sysclk(),
+            McoSrc::Hsi => HSI_FREQ,
+            McoSrc::Hse => clocks.hse(),
+            McoSrc::PllDiv2 => pll_output(Some(clocks.hse())).map_or(Hertz::from_raw(0), |f| f / 2),
+        };
+
+        if freq.raw() == 0 {
+            return Err((pin, RccError::InvalidConfig));
+        }
+
+        if freq > MAX_MCO_FREQ {
+            return Err((pin, RccError::Speed));
+        }
+
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        if src == McoSrc::Hsi {
+            free(|| rcc.ctlr.modify(|_, w| w.hsion().on()));
+            if let Err(e) = wait_for(|| rcc.ctlr.read().hsirdy().is_ready(), RccError::HsiTimeout) {
+                return Err((pin, e));
+            }
+        }
+
+        let mut pin = pin.into_alternate_push_pull();
+        pin.set_speed(OutputSpeed::HighSpeed);
+
+        free(|| rcc.cfgr0.modify(|_, w| unsafe { w.mco().bits(src as u8) }));
+
+        Ok(Self { pin })
+    }
+
+    /// Stop the clock output and give PA8 back as a floating input
+    pub fn free(self) -> PA8<Input<Floating>> {
+        let rcc = unsafe { &(*(RCC::ptr())) };
+
+        free(|| rcc.cfgr0.modify(|_, w| w.mco().no_output()));
+
+        self.pin.into_floating_input()
+    }
+}
+
+/// Poll `ready` until it returns true, at most [`RCC_TIMEOUT`] times
+fn wait_for(ready: impl FnMut() -> bool, err: RccError) -> Result<(), RccError> {
+    wait_for_polls(RCC_TIMEOUT, ready, err)
+}
+
+/// Poll `ready` until it returns true, at most `polls` times
+fn wait_for_polls(polls: u32, mut ready: impl FnMut() -> bool, err: RccError) -> Result<(), RccError> {
+    for _ in 0..polls {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(err)
+}
+
+/// Builder that searches the PLL and prescaler settings for target frequencies
+///
+/// Bus clocks that are not requested run as fast as their limits allow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ClocksBuilder {
+    hse: Option<Hertz>,
+    hse_bypass: bool,
+    sysclk: Option<Hertz>,
+    hclk: Option<Hertz>,
+    pclk1: Option<Hertz>,
+    pclk2: Option<Hertz>,
+    adcclk: Option<Hertz>,
+    usb: bool,
+    css: bool,
+}
+
+impl ClocksBuilder {
+    /// Use an external crystal of `freq` instead of the HSI, 3 to 25 MHz
+    pub fn use_hse(mut self, freq: Hertz) -> Self {
+        self.hse = Some(freq);
+        self
+    }
+
+    /// Use an external clock signal on OSC_IN instead of a crystal
+    pub fn bypass_hse(mut self) -> Self {
+        self.hse_bypass = true;
+        self
+    }
+
+    /// Run SYSCLK at exactly `freq`
+    pub fn sysclk(mut self, freq: Hertz) -> Self {
+        self.sysclk = Some(freq);
+        self
+    }
+
+    /// Run HCLK at exactly `freq`, `build` fails with
+    /// [`RccError::NoHclkSolution`] if no AHB prescaler gives it
+    pub fn hclk(mut self, freq: Hertz) -> Self {
+        self.hclk = Some(freq);
+        self
+    }
+
+    /// Run PCLK1 at exactly `freq`, `build` fails with
+    /// [`RccError::NoPclk1Solution`] if no APB1 prescaler gives it
+    pub fn pclk1(mut self, freq: Hertz) -> Self {
+        self.pclk1 = Some(freq);
+        self
+    }
+
+    /// Run PCLK2 at exactly `freq`, `build` fails with
+    /// [`RccError::NoPclk2Solution`] if no APB2 prescaler gives it
+    pub fn pclk2(mut self, freq: Hertz) -> Self {
+        self.pclk2 = Some(freq);
+        self
+    }
+
+    /// Run the ADC at exactly `freq`, `build` fails with
+    /// [`RccError::NoAdcSolution`] if no ADC prescaler gives it
+    pub fn adcclk(mut self, freq: Hertz) -> Self {
+        self.adcclk = Some(freq);
+        self
+    }
+
+    /// Require a 48 MHz USB clock, which forces SYSCLK to 48 or 72 MHz PLL
+    ///
+    /// Without [`use_hse`](Self::use_hse) the PLL runs from HSI. That is
+    /// allowed, but the HSI tolerance is wider than USB full speed accepts
+    /// over temperature, so use a crystal for a reliable link.
+    pub fn require_usb(mut self) -> Self {
+        self.usb = true;
+        self
+    }
+
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Search the clock tree for a configuration matching the targets
+    ///
+    /// Requested frequencies are met exactly or `build` fails, the others run
+    /// as fast as their limits allow.
+    pub fn build(self) -> Result<Clocks, RccError> {
+        // a divided clock is either the one asked for or the fastest allowed
+        let meets = |freq: Hertz, target: Option<Hertz>, max: Hertz| match target {
+            Some(target) => freq == target,
+            None => freq <= max,
+        };
+
+        if self.hse.is_some_and(|f| !hse_in_range(f)) {
+            return Err(RccError::HseFreq);
+        }
+        let src_freq = self.hse.unwrap_or(HSI_FREQ);
+
+        let mut clocks = Clocks {
+            hse_bypass: self.hse_bypass,
+            css: self.css,
+            ..Clocks::default()
+        };
+
+        let direct = match self.sysclk {
+            Some(f) => f == src_freq && !self.usb,
+            None => !self.usb,
+        };
+
+        if direct {
+            clocks.input_src = match self.hse {
+                Some(f) => InputSrc::Hse(f),
+                None => InputSrc::Hsi,
+            };
+        } else {
+            let targets: &[Hertz] = match self.sysclk {
+                Some(ref f) => core::slice::from_ref(f),
+                None => &[MAX_CLK_FREQ, USB_FREQ],
+            };
+
+            if targets.iter().all(|&f| f > MAX_CLK_FREQ) {
+                return Err(RccError::Speed);
+            }
+
+            let (src, pllmul) = targets
+                .iter()
+                .find_map(|&f| Self::solve_pll(self.hse, f))
+                .ok_or(RccError::NoPllSolution)?;
+            clocks.input_src = InputSrc::Pll(src);
+            clocks.pllmul = pllmul;
+
+            if self.usb {
+                clocks.usb_prediv = match clocks.sysclk() {
+                    f if f == USB_FREQ => USBPreDiv::NoDiv,
+                    f if f * 2 / 3 == USB_FREQ => USBPreDiv::Div1_5,
+                    _ => return Err(RccError::NoUsbSolution),
+                };
+            }
+        }
+        clocks.sysclk_state = clocks.input_src;
+
+        let sysclk = clocks.sysclk();
+        if sysclk > MAX_CLK_FREQ {
+            return Err(RccError::Speed);
+        }
+
+        clocks.ahb_prediv = [
+            AHBPreDiv::NoDiv,
+            AHBPreDiv::Div2,
+            AHBPreDiv::Div4,
+            AHBPreDiv::Div8,
+            AHBPreDiv::Div16,
+            AHBPreDiv::Div64,
+            AHBPreDiv::Div128,
+            AHBPreDiv::Div256,
+            AHBPreDiv::Div512,
+        ]
+        .into_iter()
+        .find(|d| meets(sysclk / d.div(), self.hclk, MAX_CLK_FREQ))
+        .ok_or(RccError::NoHclkSolution)?;
+        let hclk = clocks.hclk();
+
+        clocks.apb1_prediv = [
+            APB1PreDiv::NoDiv,
+            APB1PreDiv::Div2,
+            APB1PreDiv::Div4,
+            APB1PreDiv::Div8,
+            APB1PreDiv::Div16,
+        ]
+        .into_iter()
+        .find(|d| meets(hclk / d.div(), self.pclk1, MAX_PCLK1_FREQ))
+        .ok_or(RccError::NoPclk1Solution)?;
+
+        clocks.apb2_prediv = [
+            APB2PreDiv::NoDiv,
+            APB2PreDiv::Div2,
+            APB2PreDiv::Div4,
+            APB2PreDiv::Div8,
+            APB2PreDiv::Div16,
+        ]
+        .into_iter()
+        .find(|d| meets(hclk / d.div(), self.pclk2, MAX_CLK_FREQ))
+        .ok_or(RccError::NoPclk2Solution)?;
+        let pclk2 = clocks.pclk2();
+
+        clocks.adc_prediv = [
+            ADCPreDiv::Div2,
+            ADCPreDiv::Div4,
+            ADCPreDiv::Div6,
+            ADCPreDiv::Div8,
+        ]
+        .into_iter()
+        .find(|d| meets(pclk2 / d.div(), self.adcclk, MAX_ADC_FREQ))
+        .ok_or(RccError::NoAdcSolution)?;
+
+        clocks.validate_freq()?;
+
+        Ok(clocks)
+    }
+
+    /// Find a PLL input and multiplier producing exactly `sysclk`
+    fn solve_pll(hse: Option<Hertz>, sysclk: Hertz) -> Option<(PllSrc, PllMul)> {
+        const MULS: [PllMul; 15] = [
+            PllMul::Mul2,
+            PllMul::Mul3,
+            PllMul::Mul4,
+            PllMul::Mul5,
+            PllMul::Mul6,
+            PllMul::Mul7,
+            PllMul::Mul8,
+            PllMul::Mul9,
+            PllMul::Mul10,
+            PllMul::Mul11,
+            PllMul::Mul12,
+            PllMul::Mul13,
+            PllMul::Mul14,
+            PllMul::Mul15,
+            PllMul::Mul16,
+        ];
+
+        let srcs = match hse {
+            Some(f) if f.raw() % 2 == 0 => [Some(PllSrc::Hse(f)), Some(PllSrc::HseDiv2(f))],
+            Some(f) => [Some(PllSrc::Hse(f)), None],
+            None => [Some(PllSrc::Hsi), Some(PllSrc::HsiDiv2)],
+        };
+
+        srcs.into_iter().flatten().find_map(|src| {
+            MULS.into_iter().find_map(|pllmul| {
+                let clocks = Clocks {
+                    input_src: InputSrc::Pll(src),
+                    pllmul,
+                    ..Clocks::default()
+                };
+                (clocks.sysclk() == sysclk).then_some((src, pllmul))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hse_8mhz_to_72mhz_with_usb() {
+        let clocks = Clocks::builder()
+            .use_hse(Hertz::MHz(8))
+            .sysclk(Hertz::MHz(72))
+            .require_usb()
+            .build()
+            .unwrap();
+
+        assert_eq!(clocks.input_src, InputSrc::Pll(PllSrc::Hse(Hertz::MHz(8))));
+        assert_eq!(clocks.pllmul, PllMul::Mul9);
+        assert_eq!(clocks.usb_prediv, USBPreDiv::Div1_5);
+        assert_eq!(clocks.usb_clk(), USB_FREQ);
+    }
+
+    #[test]
+    fn hsi_with_usb() {
+        let clocks = Clocks::builder().require_usb().build().unwrap();
+
+        assert_eq!(clocks.input_src, InputSrc::Pll(PllSrc::Hsi));
+        assert_eq!(clocks.sysclk(), Hertz::MHz(72));
+        assert_eq!(clocks.usb_clk(), USB_FREQ);
+    }
+
+    #[test]
+    fn unsolvable_targets() {
+        let pll = Clocks::builder().use_hse(Hertz::MHz(25)).sysclk(Hertz::MHz(72)).build();
+        assert_eq!(pll.err(), Some(RccError::NoPllSolution));
+
+        let hclk = Clocks::builder().hclk(Hertz::MHz(7)).build();
+        assert_eq!(hclk.err(), Some(RccError::NoHclkSolution));
+
+        let adc = Clocks::builder().sysclk(Hertz::MHz(72)).adcclk(Hertz::MHz(14)).build();
+        assert_eq!(adc.err(), Some(RccError::NoAdcSolution));
+    }
+
+    #[test]
+    fn apb1_capped_at_36mhz() {
+        let clocks = Clocks::builder()
+            .use_hse(Hertz::MHz(8))
+            .sysclk(Hertz::MHz(72))
+            .build()
+            .unwrap();
+
+        assert_eq!(clocks.apb1_prediv, APB1PreDiv::Div2);
+        assert_eq!(clocks.apb2_prediv, APB2PreDiv::NoDiv);
+
+        let frozen = clocks.freeze();
+        assert_eq!(frozen.pclk1(), Hertz::MHz(36));
+        assert_eq!(frozen.pclk1_tim(), Hertz::MHz(72));
+        assert_eq!(frozen.pclk2(), Hertz::MHz(72));
+    }
+
+    #[test]
+    fn hse_out_of_range() {
+        let high = Clocks::builder().use_hse(Hertz::MHz(400)).sysclk(Hertz::MHz(72)).build();
+        assert_eq!(high.err(), Some(RccError::HseFreq));
+
+        let direct = Clocks::builder().use_hse(Hertz::MHz(40)).build();
+        assert_eq!(direct.err(), Some(RccError::HseFreq));
+
+        let low = Clocks::builder().use_hse(Hertz::MHz(2)).build();
+        assert_eq!(low.err(), Some(RccError::HseFreq));
+    }
+}
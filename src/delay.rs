@@ -1,21 +1,75 @@
 use riscv::asm::delay;
-use crate::clocks::{self, Clocks};
-pub struct Delay(usize);
+use crate::clocks::{ClockListener, FrozenClocks};
+use crate::time::{Hertz, MicrosDurationU32};
+pub struct Delay(Hertz);
 
 impl Delay {
-    pub fn new(cp: &Clocks) -> Self {
-        Self(cp.sysclk() as usize)
+    pub fn new(clocks: &FrozenClocks) -> Self {
+        Self(clocks.hclk())
     }
 
-    pub fn delay_us(&self, us: usize) {
-        unsafe { delay((us * self.0 / 1_500_000) as u32)};
+    pub fn delay_ns(&self, ns: u32) {
+        self.spin((ns as u64 * self.0.raw() as u64).div_ceil(1_500_000_000));
     }
 
-    pub fn delay_ms(&self, ms: usize) {
-        unsafe { delay((ms  * self.0 / 1_500) as u32)};
+    pub fn delay(&self, duration: MicrosDurationU32) {
+        self.delay_us(duration.to_micros());
     }
 
-    pub fn delay_s(&self, s: usize) {
-        unsafe { delay((s * self.0 / 3 * 2) as u32)};
+    pub fn delay_us(&self, us: u32) {
+        self.spin((us as u64 * self.0.raw() as u64).div_ceil(1_500_000));
+    }
+
+    pub fn delay_ms(&self, ms: u32) {
+        self.spin((ms as u64 * self.0.raw() as u64).div_ceil(1_500));
+    }
+
+    pub fn delay_s(&self, s: u32) {
+        self.spin((s as u64 * self.0.raw() as u64 * 2).div_ceil(3));
+    }
+
+    /// `riscv::asm::delay` takes a u32, split longer waits
+    ///
+    /// Callers round `loops` up, so waits are never shorter than asked.
+    fn spin(&self, mut loops: u64) {
+        while loops > 0 {
+            let n = loops.min(u32::MAX as u64) as u32;
+            unsafe { delay(n) };
+            loops -= n as u64;
+        }
+    }
+}
+
+impl ClockListener for Delay {
+    fn clocks_changed(&mut self, clocks: &FrozenClocks) {
+        self.0 = clocks.hclk();
+    }
+}
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        Delay::delay_ns(self, ns)
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us)
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms)
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl embedded_hal_02::blocking::delay::DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us)
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl embedded_hal_02::blocking::delay::DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms)
     }
 }
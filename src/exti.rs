@@ -0,0 +1,441 @@
+//! External interrupt/event controller
+//!
+//! Every function touches a single line: enables are read-modify-write of one
+//! bit inside a critical section, pending flags and software triggers are
+//! written as one set bit, which leaves the other lines alone.
+//!
+//! Lines 0..=15 are the GPIO pins selected through AFIO EXTICRx, the lines
+//! above are wired to PVD, RTC alarm and USB wakeup.
+//!
+//! Handlers for lines 0..=15 are registered with [`register`] and run by
+//! [`dispatch`]. With the `exti-handlers` feature this module defines the
+//! `EXTI0`..`EXTI4`, `EXTI9_5` and `EXTI15_10` vectors itself, otherwise call
+//! [`dispatch`] from your own:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! extern "C" fn EXTI15_10() {
+//!     exti::dispatch(10..=15);
+//! }
+//! ```
+
+use crate::gpio::Port;
+use crate::rcc::Enable;
+use ch32v1::ch32v103::{Interrupt, AFIO, EXTI, PFIC};
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+use critical_section::Mutex;
+use riscv::interrupt::free;
+
+/// Edge that sets the pending flag of a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntTrigger {
+    Rising = 0b01,
+    Falling = 0b10,
+    RisingFalling = 0b11,
+}
+
+/// `reg` with bit `line` set or cleared
+pub(crate) const fn line_value(reg: u32, line: u8, on: bool) -> u32 {
+    if on {
+        reg | (1 << line)
+    } else {
+        reg & !(1 << line)
+    }
+}
+
+/// RTENR and FTENR values selecting `trigger` on `line`
+pub(crate) const fn trigger_value(rtenr: u32, ftenr: u32, line: u8, trigger: IntTrigger) -> (u32, u32) {
+    let t = trigger as u32;
+    (
+        line_value(rtenr, line, t & 0b01 != 0),
+        line_value(ftenr, line, t & 0b10 != 0),
+    )
+}
+
+/// EXTICRx value routing `line` to `port`, each register holds 4 lines
+pub(crate) const fn exticr_value(reg: u32, line: u8, port: Port) -> u32 {
+    exticr_field(reg, line, port as u32)
+}
+
+/// EXTICRx value with the 4-bit port field of `line` set to `field`
+const fn exticr_field(reg: u32, line: u8, field: u32) -> u32 {
+    let offset = (line % 4) * 4;
+    (reg & !(0xf << offset)) | ((field & 0xf) << offset)
+}
+
+#[inline(always)]
+fn regs() -> &'static ch32v1::ch32v103::exti::RegisterBlock {
+    unsafe { &(*EXTI::ptr()) }
+}
+
+/// Read-modify-write the EXTICRx register holding GPIO line `line`
+fn modify_exticr(line: u8, f: impl Fn(u32) -> u32) {
+    let afio = unsafe { &(*AFIO::ptr()) };
+    AFIO::enable();
+
+    free(|| match line / 4 {
+        0 => afio.exticr1.modify(|r, w| unsafe { w.bits(f(r.bits())) }),
+        1 => afio.exticr2.modify(|r, w| unsafe { w.bits(f(r.bits())) }),
+        2 => afio.exticr3.modify(|r, w| unsafe { w.bits(f(r.bits())) }),
+        3 => afio.exticr4.modify(|r, w| unsafe { w.bits(f(r.bits())) }),
+        _ => {}
+    })
+}
+
+/// Route GPIO line `line` (0..=15) to `port`
+pub fn select_port(line: u8, port: Port) {
+    modify_exticr(line, |reg| exticr_value(reg, line, port))
+}
+
+/// Select the edges that set the pending flag of `line`
+pub fn set_trigger(line: u8, trigger: IntTrigger) {
+    let exti = regs();
+
+    free(|| {
+        let (rtenr, ftenr) = trigger_value(exti.rtenr.read().bits(), exti.ftenr.read().bits(), line, trigger);
+        exti.rtenr.write(|w| unsafe { w.bits(rtenr) });
+        exti.ftenr.write(|w| unsafe { w.bits(ftenr) });
+    })
+}
+
+/// Stop both edges from setting the pending flag of `line`
+pub fn clear_trigger(line: u8) {
+    let exti = regs();
+
+    free(|| {
+        exti.rtenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, false)) });
+        exti.ftenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, false)) });
+    })
+}
+
+/// Unmask the interrupt request of `line`
+pub fn listen(line: u8) {
+    let exti = regs();
+    free(|| exti.intenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, true)) }))
+}
+
+/// Mask the interrupt request of `line`
+pub fn unlisten(line: u8) {
+    let exti = regs();
+    free(|| exti.intenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, false)) }))
+}
+
+/// Unmask the event of `line`, used to wake up from WFE
+pub fn enable_event(line: u8) {
+    let exti = regs();
+    free(|| exti.evenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, true)) }))
+}
+
+/// Mask the event of `line`
+pub fn disable_event(line: u8) {
+    let exti = regs();
+    free(|| exti.evenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, false)) }))
+}
+
+/// Whether the pending flag of `line` is set
+pub fn is_pending(line: u8) -> bool {
+    regs().intfr.read().bits() & (1 << line) != 0
+}
+
+/// Pending flags of all lines
+pub fn pending() -> u32 {
+    regs().intfr.read().bits()
+}
+
+/// Clear the pending flag of `line`, INTFR is write 1 to clear
+pub fn clear_pending(line: u8) {
+    regs().intfr.write(|w| unsafe { w.bits(1 << line) })
+}
+
+/// Set the pending flag of `line` from software, cleared with [`clear_pending`]
+pub fn trigger_software(line: u8) {
+    regs().swievr.write(|w| unsafe { w.bits(1 << line) })
+}
+
+#[cfg(feature = "async")]
+/// Routing, edges and mask of a GPIO line, kept while the line is borrowed
+#[derive(Clone, Copy)]
+pub(crate) struct LineState {
+    port: u32,
+    rising: bool,
+    falling: bool,
+    listening: bool,
+}
+
+#[cfg(feature = "async")]
+impl LineState {
+    /// Whether the interrupt request of the line was unmasked
+    pub(crate) fn listening(&self) -> bool {
+        self.listening
+    }
+}
+
+#[cfg(feature = "async")]
+/// Current state of GPIO line `line`
+pub(crate) fn save(line: u8) -> LineState {
+    let exti = regs();
+    let afio = unsafe { &(*AFIO::ptr()) };
+
+    let exticr = match line / 4 {
+        0 => afio.exticr1.read().bits(),
+        1 => afio.exticr2.read().bits(),
+        2 => afio.exticr3.read().bits(),
+        _ => afio.exticr4.read().bits(),
+    };
+
+    LineState {
+        port: (exticr >> ((line % 4) * 4)) & 0xf,
+        rising: exti.rtenr.read().bits() & (1 << line) != 0,
+        falling: exti.ftenr.read().bits() & (1 << line) != 0,
+        listening: exti.intenr.read().bits() & (1 << line) != 0,
+    }
+}
+
+#[cfg(feature = "async")]
+/// Put GPIO line `line` back the way [`save`] found it
+pub(crate) fn restore(line: u8, state: LineState) {
+    let exti = regs();
+
+    modify_exticr(line, |reg| exticr_field(reg, line, state.port));
+    free(|| {
+        exti.rtenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, state.rising)) });
+        exti.ftenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, state.falling)) });
+        exti.intenr.modify(|r, w| unsafe { w.bits(line_value(r.bits(), line, state.listening)) });
+    })
+}
+
+/// Code run by [`dispatch`] when a GPIO line fires
+pub enum Handler {
+    /// Plain function
+    Callback(fn()),
+    /// Closure with `'static` state, e.g. from a `static_cell`
+    Closure(&'static mut (dyn FnMut() + Send)),
+}
+
+impl Handler {
+    fn call(&mut self) {
+        match self {
+            Handler::Callback(f) => f(),
+            Handler::Closure(f) => f(),
+        }
+    }
+}
+
+/// Handler of a line, `generation` changes whenever the handler is replaced
+struct Slot {
+    handler: Option<Handler>,
+    generation: u32,
+}
+
+const EMPTY: Slot = Slot {
+    handler: None,
+    generation: 0,
+};
+static HANDLERS: Mutex<RefCell<[Slot; 16]>> = Mutex::new(RefCell::new([EMPTY; 16]));
+
+/// Replace the handler of `line`, returning the previous one
+fn set_handler(line: u8, handler: Option<Handler>) -> Option<Handler> {
+    critical_section::with(|cs| {
+        let slot = &mut HANDLERS.borrow_ref_mut(cs)[line as usize];
+        // tells a running dispatch not to put its handler back
+        slot.generation = slot.generation.wrapping_add(1);
+        core::mem::replace(&mut slot.handler, handler)
+    })
+}
+
+/// Call the handler of `line` outside the critical section
+///
+/// The handler is taken out of its slot while it runs and only put back if it
+/// did not register or unregister a handler for the line itself.
+fn run_handler(line: u8) {
+    let taken = critical_section::with(|cs| {
+        let slot = &mut HANDLERS.borrow_ref_mut(cs)[line as usize];
+        slot.handler.take().map(|handler| (handler, slot.generation))
+    });
+
+    if let Some((mut handler, generation)) = taken {
+        handler.call();
+        critical_section::with(|cs| {
+            let slot = &mut HANDLERS.borrow_ref_mut(cs)[line as usize];
+            if slot.generation == generation {
+                slot.handler = Some(handler);
+            }
+        });
+    }
+}
+
+/// PFIC vector shared by GPIO line `line`
+pub(crate) const fn vector(line: u8) -> Interrupt {
+    match line {
+        0 => Interrupt::EXTI0,
+        1 => Interrupt::EXTI1,
+        2 => Interrupt::EXTI2,
+        3 => Interrupt::EXTI3,
+        4 => Interrupt::EXTI4,
+        5..=9 => Interrupt::EXTI9_5,
+        _ => Interrupt::EXTI15_10,
+    }
+}
+
+/// Set the PFIC enable bit of `irq`, IENRx ignores zeros
+pub(crate) fn enable_vector(irq: Interrupt) {
+    let pfic = unsafe { &(*PFIC::ptr()) };
+    let n = irq as u16;
+
+    if n < 32 {
+        pfic.ienr1.write(|w| unsafe { w.bits(1 << n) });
+    } else {
+        pfic.ienr2.write(|w| unsafe { w.bits(1 << (n - 32)) });
+    }
+}
+
+/// Run `handler` whenever GPIO line `line` (0..=15) is pending
+///
+/// Replaces the previous handler of the line and enables its PFIC vector. The
+/// line itself still has to be routed and unmasked, see
+/// [`GpioPin::on_interrupt`](crate::gpio::GpioPin::on_interrupt).
+pub fn register(line: u8, handler: Handler) {
+    set_handler(line, Some(handler));
+    enable_vector(vector(line));
+}
+
+/// Drop the handler of GPIO line `line`, the vector stays enabled as other
+/// lines may share it
+///
+/// Called from the handler of `line` itself this returns `None`, the running
+/// handler is dropped once it returns.
+pub fn unregister(line: u8) -> Option<Handler> {
+    set_handler(line, None)
+}
+
+/// Clear and handle the unmasked pending lines in `lines`
+///
+/// With the `async` feature this also wakes pins waiting on the lines. Each
+/// handler runs outside the critical section, so it may register,
+/// unregister or reconfigure lines.
+pub fn dispatch(lines: RangeInclusive<u8>) {
+    let exti = regs();
+    let pending = exti.intfr.read().bits() & exti.intenr.read().bits();
+
+    for line in lines.filter(|line| pending & (1 << line) != 0) {
+        clear_pending(line);
+
+        #[cfg(feature = "async")]
+        crate::gpio::wait::on_edge(line);
+
+        run_handler(line);
+    }
+}
+
+#[cfg(feature = "exti-handlers")]
+mod handlers {
+    use super::dispatch;
+
+    macro_rules! handlers {
+        ($($NAME:ident => $lines:expr,)+) => {
+            $(
+                #[no_mangle]
+                extern "C" fn $NAME() {
+                    dispatch($lines);
+                }
+            )+
+        };
+    }
+
+    handlers! {
+        EXTI0 => 0..=0,
+        EXTI1 => 1..=1,
+        EXTI2 => 2..=2,
+        EXTI3 => 3..=3,
+        EXTI4 => 4..=4,
+        EXTI9_5 => 5..=9,
+        EXTI15_10 => 10..=15,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn line_value_keeps_other_lines() {
+        let reg = 0xa5a5_5a5a;
+        for line in 0..20 {
+            let on = line_value(reg, line, true);
+            let off = line_value(reg, line, false);
+            assert_eq!(on & !(1 << line), reg & !(1 << line));
+            assert_eq!(off & !(1 << line), reg & !(1 << line));
+            assert_ne!(on & (1 << line), 0);
+            assert_eq!(off & (1 << line), 0);
+        }
+    }
+
+    #[test]
+    fn trigger_value_sets_both_edge_registers() {
+        let (r, f) = trigger_value(0x0000_00f0, 0x0000_0f00, 2, IntTrigger::Rising);
+        assert_eq!((r, f), (0x0000_00f4, 0x0000_0f00));
+        let (r, f) = trigger_value(0x0000_00f4, 0x0000_0f00, 2, IntTrigger::Falling);
+        assert_eq!((r, f), (0x0000_00f0, 0x0000_0f04));
+        let (r, f) = trigger_value(0, 0, 9, IntTrigger::RisingFalling);
+        assert_eq!((r, f), (1 << 9, 1 << 9));
+    }
+
+    #[test]
+    fn exticr_value_touches_only_line_field() {
+        let reg = 0x0000_3210;
+        assert_eq!(exticr_value(reg, 0, Port::GPIOD), 0x0000_3213);
+        assert_eq!(exticr_value(reg, 5, Port::GPIOA), 0x0000_3200);
+        assert_eq!(exticr_value(reg, 11, Port::GPIOC), 0x0000_2210);
+        assert_eq!(exticr_value(reg, 14, Port::GPIOB), 0x0000_3110);
+    }
+
+    #[test]
+    fn handler_changes_from_callback_are_kept() {
+        const LINE: u8 = 12;
+        static CALLS: AtomicU32 = AtomicU32::new(0);
+
+        fn stays() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }
+        fn unregisters() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            assert!(unregister(LINE).is_none());
+        }
+        fn replaces() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            set_handler(LINE, Some(Handler::Callback(unregisters)));
+        }
+        fn reregisters() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            unregister(LINE);
+            set_handler(LINE, Some(Handler::Callback(replaces)));
+        }
+        let calls = || CALLS.load(Ordering::Relaxed);
+
+        // a handler that leaves its slot alone runs every time
+        set_handler(LINE, Some(Handler::Callback(stays)));
+        run_handler(LINE);
+        run_handler(LINE);
+        assert_eq!(calls(), 2);
+
+        // unregistering from the callback sticks
+        set_handler(LINE, Some(Handler::Callback(unregisters)));
+        run_handler(LINE);
+        run_handler(LINE);
+        assert_eq!(calls(), 3);
+        assert!(unregister(LINE).is_none());
+
+        // so do registering and re-registering
+        set_handler(LINE, Some(Handler::Callback(reregisters)));
+        run_handler(LINE);
+        assert_eq!(calls(), 4);
+        run_handler(LINE);
+        assert_eq!(calls(), 5);
+        run_handler(LINE);
+        run_handler(LINE);
+        assert_eq!(calls(), 6);
+        assert!(unregister(LINE).is_none());
+    }
+}
@@ -1,8 +1,25 @@
-use ch32v1::ch32v103::{self as pac, AFIO, EXTI};
+use ch32v1::ch32v103 as pac;
+use crate::exti;
+use crate::rcc::{Enable, Reset};
 use core::convert::Infallible;
-use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+use core::marker::PhantomData;
 use riscv::interrupt::free;
 
+pub mod alt;
+mod dynamic;
+mod erased;
+mod hal_1;
+#[cfg(feature = "embedded-hal-02")]
+mod hal_02;
+mod port;
+#[cfg(feature = "async")]
+pub(crate) mod wait;
+
+pub use crate::exti::IntTrigger;
+pub use dynamic::{DynamicPin, PinModeError};
+pub use erased::{ErasedPin, PartiallyErasedPin};
+pub use port::{PinGroup, PinSet, PortWriter};
+
 /// choose which group of GPIO you want to use
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Port {
@@ -30,14 +47,15 @@ pub enum OutputType {
     OpenDrain,
 }
 
+/// Maximum output speed, it's the MODE field value of an output
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutputSpeed {
     /// GPIO 2Mhz speed
-    LowSpeed = 1,
+    LowSpeed = 0b10,
     /// GPIO 10Mhz speed
-    MediumSpeed,
+    MediumSpeed = 0b01,
     /// GPIO 50Mhz speed
-    HighSpeed,
+    HighSpeed = 0b11,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,17 +64,13 @@ pub enum Pull {
     PullDown,
 }
 
+/// Failure of the LCKR lock key sequence
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CfgLock {
-    Unlock,
-    Lock,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum IntTrigger {
-    Rising = 0b01,
-    Falling = 0b10,
-    RisingFalling = 0b11,
+pub enum LockError {
+    /// LCKK was already set, the port stays frozen until the next reset
+    AlreadyLocked,
+    /// The key sequence was not accepted, LCKK did not read back as set
+    KeySequence,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -64,6 +78,79 @@ pub enum PinState {
     Low,
     High,
 }
+
+/// Complete configuration of a pin, i.e. its CNF/MODE nibble plus the pull
+/// direction kept in OUTDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinConfig {
+    FloatingInput,
+    PullUpInput,
+    PullDownInput,
+    Analog,
+    /// General purpose output
+    Output(OutputType, OutputSpeed),
+    /// Alternate function output
+    Alternate(OutputType, OutputSpeed),
+}
+
+impl PinConfig {
+    /// CNF[1:0] MODE[1:0] nibble
+    pub const fn bits(&self) -> u32 {
+        match self {
+            Self::Analog => 0b0000,
+            Self::FloatingInput => 0b0100,
+            Self::PullUpInput | Self::PullDownInput => 0b1000,
+            Self::Output(otype, speed) => ((*otype as u32) << 2) | *speed as u32,
+            Self::Alternate(otype, speed) => 0b1000 | ((*otype as u32) << 2) | *speed as u32,
+        }
+    }
+
+    /// Decode a CNF/MODE nibble, `odr` is the OUTDR bit selecting the pull direction
+    pub const fn from_bits(bits: u32, odr: bool) -> Self {
+        let otype = if bits & 0b0100 == 0 {
+            OutputType::PushPull
+        } else {
+            OutputType::OpenDrain
+        };
+        let speed = match bits & 0b11 {
+            0b01 => OutputSpeed::MediumSpeed,
+            0b10 => OutputSpeed::LowSpeed,
+            _ => OutputSpeed::HighSpeed,
+        };
+
+        match (bits & 0b11, bits & 0b1100) {
+            (0b00, 0b0000) => Self::Analog,
+            (0b00, 0b1000) if odr => Self::PullUpInput,
+            (0b00, 0b1000) => Self::PullDownInput,
+            (0b00, _) => Self::FloatingInput,
+            (_, 0b0000 | 0b0100) => Self::Output(otype, speed),
+            _ => Self::Alternate(otype, speed),
+        }
+    }
+
+    /// OUTDR level needed by a pulled input
+    const fn pull(&self) -> Option<PinState> {
+        match self {
+            Self::PullUpInput => Some(PinState::High),
+            Self::PullDownInput => Some(PinState::Low),
+            _ => None,
+        }
+    }
+}
+
+/// `cfgr` (CFGLR for pins 0..=7, CFGHR for 8..=15) with the nibble of `pin` replaced by `cfg`
+const fn cfgr_value(cfgr: u32, pin: u8, cfg: u32) -> u32 {
+    let offset = 4 * (pin & 0x07) as u32;
+    (cfgr & !(0x0f << offset)) | ((cfg & 0x0f) << offset)
+}
+
+/// BSHR value driving `pin` to `state`, bits 0..=15 set and 16..=31 reset
+const fn bshr_value(pin: u8, state: PinState) -> u32 {
+    match state {
+        PinState::High => 1 << pin,
+        PinState::Low => 1 << (pin + 16),
+    }
+}
 /// GPIO Pin abstract structure
 pub struct Pin {
     port: Port,
@@ -76,193 +163,124 @@ impl Pin {
         _regs(&self.port)
     }
 
-    pub fn new(port: Port, pin: u8, mode: PinMode) -> Self {
-        // assert!(pin <= 15, "Pin range: 0~15");
-        free(|| {
-            let rcc = unsafe { &(*pac::RCC::ptr()) };
+    /// Apply `cfg` to the pin
+    ///
+    /// The pull direction is written to OUTDR before the nibble so that a
+    /// pulled input never floats or pulls the wrong way in between.
+    pub fn configure(&self, cfg: PinConfig) {
+        let reg = unsafe { &(*self.regs()) };
 
-            match port {
-                Port::GPIOA => {
-                    if rcc.apb2pcenr.read().iopaen().bit_is_clear() {
-                        rcc.apb2pcenr.modify(|_, w| w.iopaen().set_bit())
-                    }
-                }
-                Port::GPIOB => {
-                    if rcc.apb2pcenr.read().iopben().bit_is_clear() {
-                        rcc.apb2pcenr.modify(|_, w| w.iopben().set_bit())
-                    }
-                }
-                Port::GPIOC => {
-                    if rcc.apb2pcenr.read().iopcen().bit_is_clear() {
-                        rcc.apb2pcenr.modify(|_, w| w.iopcen().set_bit())
-                    }
-                }
-                Port::GPIOD => {
-                    if rcc.apb2pcenr.read().iopden().bit_is_clear() {
-                        rcc.apb2pcenr.modify(|_, w| w.iopden().set_bit())
-                    }
-                }
+        if let Some(state) = cfg.pull() {
+            reg.bshr.write(|w| unsafe { w.bits(bshr_value(self.pin, state)) });
+        }
+
+        free(|| {
+            if self.pin < 8 {
+                reg.cfglr.modify(|r, w| unsafe { w.bits(cfgr_value(r.bits(), self.pin, cfg.bits())) })
+            } else {
+                reg.cfghr.modify(|r, w| unsafe { w.bits(cfgr_value(r.bits(), self.pin, cfg.bits())) })
             }
-        });
+        })
+    }
 
-        let pin = Self {
-            port: port,
-            pin: pin,
+    /// Current configuration read back from CFGxR and OUTDR
+    pub fn config(&self) -> PinConfig {
+        let reg = unsafe { &(*self.regs()) };
+        let offset = 4 * (self.pin & 0x07);
+        let cfgr = if self.pin < 8 {
+            reg.cfglr.read().bits()
+        } else {
+            reg.cfghr.read().bits()
         };
+        let odr = reg.outdr.read().bits() & (1 << self.pin) != 0;
+
+        PinConfig::from_bits((cfgr >> offset) & 0x0f, odr)
+    }
+
+    pub fn new(port: Port, pin: u8, mode: PinMode) -> Self {
+        // assert!(pin <= 15, "Pin range: 0~15");
+        match port {
+            Port::GPIOA => pac::GPIOA::enable(),
+            Port::GPIOB => pac::GPIOB::enable(),
+            Port::GPIOC => pac::GPIOC::enable(),
+            Port::GPIOD => pac::GPIOD::enable(),
+        }
+
+        let pin = Self { port, pin };
 
         pin.mode(mode);
 
         pin
     }
 
+    /// Switch between input and output, keeping the rest of the configuration
+    /// when the pin already is in that mode
     pub fn mode(&self, mode: PinMode) {
-        let reg = unsafe { &(*self.regs()) };
-        free(|| {
-            let offset = 4 * (self.pin & !(0x01 << 3));
-            if self.pin >> 3 == 0 {
-                reg.cfglr.modify(|r, w| {
-                    let val = r.bits() & !(0x03 << offset) | ((mode as u32) << offset);
-                    unsafe { w.bits(val) }
-                })
-            } else {
-                reg.cfghr.modify(|r, w| {
-                    let val = r.bits() & !(0x03 << offset) | ((mode as u32) << offset);
-                    unsafe { w.bits(val) }
-                })
-            }
-        })
+        let cfg = match (mode, self.config()) {
+            (PinMode::Input, cfg @ (PinConfig::PullUpInput | PinConfig::PullDownInput)) => cfg,
+            (PinMode::Input, _) => PinConfig::FloatingInput,
+            (PinMode::Output, cfg @ (PinConfig::Output(..) | PinConfig::Alternate(..))) => cfg,
+            (PinMode::Output, _) => PinConfig::Output(OutputType::PushPull, OutputSpeed::LowSpeed),
+        };
+        self.configure(cfg);
     }
 
+    /// Set the output type, ignored while the pin is an input
     pub fn output_type(&self, val: OutputType) {
-        let reg = unsafe { &(*self.regs()) };
-        free(|| {
-            let offset = 4 * (self.pin & !(0x01 << 3));
-            if self.pin >> 3 == 0 {
-                reg.cfglr.modify(|r, w| {
-                    let bits = r.bits() & !(0x0c << offset) | ((val as u32) << (offset + 2));
-                    unsafe { w.bits(bits) }
-                })
-            }
-            if self.pin >> 3 == 1 {
-                let offset = 4 * (self.pin - 8);
-                reg.cfghr.modify(|r, w| {
-                    let bits = r.bits() & !(0x0c << offset) | ((val as u32) << (offset + 2));
-                    unsafe { w.bits(bits) }
-                })
-            }
-        })
+        match self.config() {
+            PinConfig::Output(_, speed) => self.configure(PinConfig::Output(val, speed)),
+            PinConfig::Alternate(_, speed) => self.configure(PinConfig::Alternate(val, speed)),
+            _ => {}
+        }
     }
 
+    /// Set the output speed, ignored while the pin is an input
     pub fn output_speed(&self, speed: OutputSpeed) {
-        let reg = unsafe { &(*(self.regs())) };
-
-        free(|| {
-            if self.pin >> 3 == 0 {
-                let offset = 4 * (self.pin & !(0x01 << 3));
-                reg.cfglr.modify(|r, w| {
-                    let bits = r.bits() & !(0x03 << offset) | (speed as u32);
-                    unsafe { w.bits(bits) }
-                })
-            }
-            if self.pin >> 3 == 1 {
-                let offset = 4 * (self.pin - 8);
-                reg.cfghr.modify(|r, w| {
-                    let bits = r.bits() & !(0x03 << offset) | (speed as u32);
-                    unsafe { w.bits(bits) }
-                })
-            }
-        })
+        match self.config() {
+            PinConfig::Output(otype, _) => self.configure(PinConfig::Output(otype, speed)),
+            PinConfig::Alternate(otype, _) => self.configure(PinConfig::Alternate(otype, speed)),
+            _ => {}
+        }
     }
 
+    /// Turn an input into a pulled input, ignored while the pin is an output
     pub fn pull(&self, value: Pull) {
-        let reg = unsafe { &(*(self.regs())) };
-
-        free(|| {
-            let offset = 4 * (self.pin & !(0x01 << 3));
-
-            let mut current_mode = 0x00;
-
-            if self.pin >> 3 == 0 {
-                current_mode = (reg.cfglr.read().bits() & (0x03 << offset)) >> offset;
-            } else if self.pin >> 3 == 1 {
-                current_mode = (reg.cfghr.read().bits() & (0x03 << offset)) >> offset;
-            }
-
-            if current_mode == PinMode::Input as u32 {
-                reg.outdr.modify(|r, w| {
-                    let bits = r.bits() & !(0x01 << self.pin) | ((value as u32) << self.pin);
-                    unsafe { w.bits(bits) }
+        match self.config() {
+            PinConfig::FloatingInput | PinConfig::PullUpInput | PinConfig::PullDownInput => {
+                self.configure(match value {
+                    Pull::PullUp => PinConfig::PullUpInput,
+                    Pull::PullDown => PinConfig::PullDownInput,
                 })
             }
-        })
+            _ => {}
+        }
     }
 
-    pub fn cfg_lock(&self, value: CfgLock) {
-        let reg = unsafe { &(*(self.regs())) };
-
-        free(|| {
-            if reg.lckr.read().lckk().is_unlocked() {
-                reg.lckr.modify(|_, w| w.lckk().locked())
-            }
-            todo!()
-        })
+    /// Freeze the configuration of this pin until the next reset
+    ///
+    /// The lock key can be used once per port, lock all pins of a port that
+    /// need it together, see [`Lockable`] for tuples of pins.
+    pub fn cfg_lock(&self) -> Result<(), LockError> {
+        lock_pins(self.port, 1 << self.pin)
     }
 
+    /// Route EXTI line `pin` to this pin and unmask its interrupt on `trigger`
     pub fn enable_int(&self, trigger: IntTrigger) {
-        let afio = unsafe { &(*AFIO::ptr()) };
-        let offset = (self.pin & 0x03) * 4;
-        let exti = unsafe { &(*(EXTI::ptr())) };
-
-        free(|| {
-            // Set AFIO EXTICRx to enable gpio exti line
-            if self.pin <= 3 {
-                afio.exticr1
-                    .modify(|_, w| unsafe { w.bits((self.port as u32) << offset) })
-            } else if self.pin <= 7 {
-                afio.exticr2
-                    .modify(|_, w| unsafe { w.bits((self.port as u32) << offset) })
-            } else if self.pin <= 11 {
-                afio.exticr3
-                    .modify(|_, w| unsafe { w.bits((self.port as u32) << offset) })
-            } else if self.pin <= 15 {
-                afio.exticr4
-                    .modify(|_, w| unsafe { w.bits((self.port as u32) << offset) })
-            }
-
-            // enable exti for pinX
-            exti.intenr
-                .modify(|_, w| unsafe { w.bits(0x01 << self.pin) });
-            // enable interrupt event for pinX
-            exti.evenr
-                .modify(|_, w| unsafe { w.bits(0x01 << self.pin) });
-            // set rising trigger
-            exti.rtenr
-                .modify(|_, w| unsafe { w.bits((trigger as u32 & 0x01) << self.pin) });
-            // set falling trigger
-            exti.ftenr
-                .modify(|_, w| unsafe { w.bits((trigger as u32 >> 1) << self.pin) });
-        })
+        exti::select_port(self.pin, self.port);
+        exti::set_trigger(self.pin, trigger);
+        exti::listen(self.pin);
     }
 
+    /// Mask the interrupt of this pin's EXTI line and clear its triggers
     pub fn disable_int(&self) {
-        let exti = unsafe { &(*(EXTI::ptr())) };
-
-        free(|| {
-            // enable exti for pinX
-            exti.intenr.modify(|_, w| unsafe { w.bits(0 << self.pin) });
-            // enable interrupt event for pinX
-            exti.evenr.modify(|_, w| unsafe { w.bits(0 << self.pin) });
-            // set rising trigger
-            exti.rtenr.modify(|_, w| unsafe { w.bits(0 << self.pin) });
-            // set falling trigger
-            exti.ftenr.modify(|_, w| unsafe { w.bits(0 << self.pin) });
-        })
+        exti::unlisten(self.pin);
+        exti::clear_trigger(self.pin);
     }
 
     pub fn get_state(&self) -> PinState {
         let reg = unsafe { &(*(self.regs())) };
         free(|| {
-            let state = (reg.indr.read().bits() & !(0x01 << self.pin)) >> self.pin;
+            let state = (reg.indr.read().bits() & (0x01 << self.pin)) >> self.pin;
 
             if state == 1 {
                 PinState::High
@@ -275,28 +293,25 @@ impl Pin {
     pub fn set_state(&self, value: PinState) {
         let reg = unsafe { &(*(self.regs())) };
 
-        free(|| match value {
-            PinState::Low => reg.bcr.write(|w| unsafe { w.bits(0x01 << self.pin) }),
-            PinState::High => reg.bshr.write(|w| unsafe { w.bits(0x01 << self.pin) }),
-        })
+        // BSHR is write-only and per bit, no critical section needed
+        reg.bshr.write(|w| unsafe { w.bits(bshr_value(self.pin, value)) })
     }
 
     pub fn is_high(&self) -> bool {
         let reg = unsafe { &(*(self.regs())) };
-        free(|| {
-            let state = (reg.indr.read().bits() & (0x01 << self.pin)) >> self.pin;
-            if state == 0 {
-                false
-            } else {
-                true
-            }
-        })
+        reg.indr.read().bits() & (0x01 << self.pin) != 0
     }
 
     pub fn is_low(&self) -> bool {
         !self.is_high()
     }
 
+    /// Whether OUTDR drives the pin low, as opposed to the level seen on it
+    pub(crate) fn is_set_low(&self) -> bool {
+        let reg = unsafe { &(*(self.regs())) };
+        reg.outdr.read().bits() & (0x01 << self.pin) == 0
+    }
+
     pub fn set_high(&self) {
         self.set_state(PinState::High);
     }
@@ -306,7 +321,7 @@ impl Pin {
     }
 
     pub fn toggle(&self) {
-        if self.is_high() {
+        if !self.is_set_low() {
             self.set_low();
         } else {
             self.set_high();
@@ -314,59 +329,603 @@ impl Pin {
     }
 }
 
-impl InputPin for Pin {
-    type Error = Infallible;
+const LCKK: u32 = 1 << 16;
+
+/// Lock the configuration of the pins in `mask` on `port` until the next reset
+///
+/// Runs the LCKK write 1/write 0/write 1/read/read key sequence. Once it has
+/// completed LCKR is frozen too, so pins left out of `mask` can no longer be
+/// locked.
+pub(crate) fn lock_pins(port: Port, mask: u16) -> Result<(), LockError> {
+    let lckr = unsafe { &(*_regs(&port)).lckr };
+
+    free(|| {
+        if lckr.read().bits() & LCKK != 0 {
+            return Err(LockError::AlreadyLocked);
+        }
+
+        lckr.write(|w| unsafe { w.bits(LCKK | mask as u32) });
+        lckr.write(|w| unsafe { w.bits(mask as u32) });
+        lckr.write(|w| unsafe { w.bits(LCKK | mask as u32) });
+        let _ = lckr.read();
+
+        if lckr.read().bits() & LCKK != 0 {
+            Ok(())
+        } else {
+            Err(LockError::KeySequence)
+        }
+    })
+}
 
-    fn is_high(&self) -> Result<bool, Self::Error> {
-        Ok(Pin::is_high(&self))
+const fn _regs(port: &Port) -> *const pac::gpioa::RegisterBlock {
+    match port {
+        Port::GPIOA => pac::GPIOA::ptr(),
+        Port::GPIOB => pac::GPIOB::ptr(),
+        Port::GPIOC => pac::GPIOC::ptr(),
+        Port::GPIOD => pac::GPIOD::ptr(),
     }
+}
+
+/// Extension trait to split a GPIO peripheral into independent typestate pins
+pub trait GpioExt {
+    type Parts;
 
-    fn is_low(&self) -> Result<bool, Self::Error> {
-        Ok(Pin::is_low(&self))
+    /// Enable and reset the port, then hand out all of its pins as floating inputs
+    fn split(self) -> Self::Parts;
+}
+
+/// Input mode (type state)
+pub struct Input<PULL = Floating>(PhantomData<PULL>);
+
+/// Floating input (type state)
+pub struct Floating;
+
+/// Pulled up input (type state)
+pub struct PullUp;
+
+/// Pulled down input (type state)
+pub struct PullDown;
+
+/// Output mode (type state)
+pub struct Output<OTYPE = PushPull>(PhantomData<OTYPE>);
+
+/// Push-pull output (type state)
+pub struct PushPull;
+
+/// Open drain output (type state)
+pub struct OpenDrain;
+
+/// Alternate function output mode (type state)
+pub struct Alternate<OTYPE = PushPull>(PhantomData<OTYPE>);
+
+/// Analog mode (type state)
+pub struct Analog;
+
+/// Pin whose configuration is frozen by LCKR, `MODE` is the mode it was locked in
+/// (type state)
+///
+/// `Locked` is not a [`Mode`], so none of the `into_*` or `set_speed` methods
+/// exist for it. The output data register is not locked, outputs can still be
+/// driven.
+pub struct Locked<MODE>(PhantomData<MODE>);
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Pin mode that can be written to CFGxR
+pub trait Mode: sealed::Sealed {
+    #[doc(hidden)]
+    const CFG: PinConfig;
+}
+
+macro_rules! pin_mode {
+    ($($MODE:ty => $cfg:expr,)+) => {
+        $(
+            impl sealed::Sealed for $MODE {}
+            impl Mode for $MODE {
+                const CFG: PinConfig = $cfg;
+            }
+        )+
+    };
+}
+
+// outputs start at 2 MHz, see `set_speed`
+pin_mode! {
+    Input<Floating> => PinConfig::FloatingInput,
+    Input<PullUp> => PinConfig::PullUpInput,
+    Input<PullDown> => PinConfig::PullDownInput,
+    Output<PushPull> => PinConfig::Output(OutputType::PushPull, OutputSpeed::LowSpeed),
+    Output<OpenDrain> => PinConfig::Output(OutputType::OpenDrain, OutputSpeed::LowSpeed),
+    Alternate<PushPull> => PinConfig::Alternate(OutputType::PushPull, OutputSpeed::LowSpeed),
+    Alternate<OpenDrain> => PinConfig::Alternate(OutputType::OpenDrain, OutputSpeed::LowSpeed),
+    Analog => PinConfig::Analog,
+}
+
+/// Typestate GPIO pin, `P` is the port letter and `N` the pin number
+///
+/// Pins are obtained through [`GpioExt::split`] and are usually named by their
+/// aliases, e.g. `PA0<Output<PushPull>>`.
+pub struct GpioPin<const P: char, const N: u8, MODE = Input<Floating>> {
+    _mode: PhantomData<MODE>,
+}
+
+impl<const P: char, const N: u8, MODE> GpioPin<P, N, MODE> {
+    const fn new() -> Self {
+        Self { _mode: PhantomData }
+    }
+
+    const fn port() -> Port {
+        match P {
+            'A' => Port::GPIOA,
+            'B' => Port::GPIOB,
+            'C' => Port::GPIOC,
+            _ => Port::GPIOD,
+        }
+    }
+
+    /// Untyped view of this pin, used to reach the registers
+    pub(crate) const fn raw(&self) -> Pin {
+        Pin {
+            port: Self::port(),
+            pin: N,
+        }
+    }
+
+    #[inline(always)]
+    fn regs(&self) -> &pac::gpioa::RegisterBlock {
+        unsafe { &(*_regs(&Self::port())) }
+    }
+
+    #[inline(always)]
+    fn _set_high(&self) {
+        self.regs().bshr.write(|w| unsafe { w.bits(bshr_value(N, PinState::High)) })
+    }
+
+    #[inline(always)]
+    fn _set_low(&self) {
+        self.regs().bshr.write(|w| unsafe { w.bits(bshr_value(N, PinState::Low)) })
+    }
+
+    #[inline(always)]
+    fn _is_set_low(&self) -> bool {
+        self.regs().outdr.read().bits() & (1 << N) == 0
+    }
+
+    #[inline(always)]
+    fn _is_low(&self) -> bool {
+        self.regs().indr.read().bits() & (1 << N) == 0
     }
 }
 
-impl OutputPin for Pin {
-    type Error = Infallible;
+impl<const P: char, const N: u8, MODE: Mode> GpioPin<P, N, MODE> {
+    fn into_mode<NEW: Mode>(self) -> GpioPin<P, N, NEW> {
+        self.raw().configure(NEW::CFG);
+        GpioPin::new()
+    }
+
+    pub fn into_floating_input(self) -> GpioPin<P, N, Input<Floating>> {
+        self.into_mode()
+    }
+
+    pub fn into_pull_up_input(self) -> GpioPin<P, N, Input<PullUp>> {
+        self.into_mode()
+    }
+
+    pub fn into_pull_down_input(self) -> GpioPin<P, N, Input<PullDown>> {
+        self.into_mode()
+    }
 
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        Ok(Pin::set_high(&self))
+    pub fn into_push_pull_output(self) -> GpioPin<P, N, Output<PushPull>> {
+        self.into_push_pull_output_with_state(PinState::Low)
     }
 
-    fn set_low(&mut self) -> Result<(), Self::Error> {
-        Ok(Pin::set_low(&self))
+    /// Configure as push-pull output, driving `state` from the first cycle
+    pub fn into_push_pull_output_with_state(self, state: PinState) -> GpioPin<P, N, Output<PushPull>> {
+        match state {
+            PinState::Low => self._set_low(),
+            PinState::High => self._set_high(),
+        }
+        self.into_mode()
     }
 
-    fn set_state(&mut self, state: embedded_hal::digital::v2::PinState) -> Result<(), Self::Error> {
+    pub fn into_open_drain_output(self) -> GpioPin<P, N, Output<OpenDrain>> {
+        self.into_open_drain_output_with_state(PinState::High)
+    }
+
+    /// Configure as open drain output, driving `state` from the first cycle
+    pub fn into_open_drain_output_with_state(self, state: PinState) -> GpioPin<P, N, Output<OpenDrain>> {
         match state {
-            embedded_hal::digital::v2::PinState::High => Ok(Pin::set_state(&self, PinState::High)),
-            embedded_hal::digital::v2::PinState::Low => Ok(Pin::set_state(&self, PinState::Low)),
+            PinState::Low => self._set_low(),
+            PinState::High => self._set_high(),
         }
+        self.into_mode()
+    }
+
+    pub fn into_alternate_push_pull(self) -> GpioPin<P, N, Alternate<PushPull>> {
+        self.into_mode()
+    }
+
+    pub fn into_alternate_open_drain(self) -> GpioPin<P, N, Alternate<OpenDrain>> {
+        self.into_mode()
+    }
+
+    pub fn into_analog(self) -> GpioPin<P, N, Analog> {
+        self.into_mode()
     }
 }
 
-impl ToggleableOutputPin for Pin {
-    type Error = Infallible;
+impl<const P: char, const N: u8, OTYPE> GpioPin<P, N, Output<OTYPE>>
+where
+    Output<OTYPE>: Mode,
+{
+    /// Change the maximum output speed, `into_*` conversions reset it to 2 MHz
+    pub fn set_speed(&mut self, speed: OutputSpeed) {
+        if let PinConfig::Output(otype, _) = <Output<OTYPE> as Mode>::CFG {
+            self.raw().configure(PinConfig::Output(otype, speed));
+        }
+    }
+}
 
-    fn toggle(&mut self) -> Result<(), Self::Error> {
-        Ok(Pin::toggle(&self))
+impl<const P: char, const N: u8, OTYPE> GpioPin<P, N, Alternate<OTYPE>>
+where
+    Alternate<OTYPE>: Mode,
+{
+    /// Change the maximum output speed, `into_*` conversions reset it to 2 MHz
+    pub fn set_speed(&mut self, speed: OutputSpeed) {
+        if let PinConfig::Alternate(otype, _) = <Alternate<OTYPE> as Mode>::CFG {
+            self.raw().configure(PinConfig::Alternate(otype, speed));
+        }
     }
 }
 
-impl StatefulOutputPin for Pin {
-    fn is_set_high(&self) -> Result<bool, Self::Error> {
-        Ok(Pin::is_high(&self))
+impl<const P: char, const N: u8, OTYPE> GpioPin<P, N, Output<OTYPE>> {
+    #[inline]
+    pub fn set_high(&mut self) {
+        self._set_high()
+    }
+
+    #[inline]
+    pub fn set_low(&mut self) {
+        self._set_low()
     }
 
-    fn is_set_low(&self) -> Result<bool, Self::Error> {
-        Ok(Pin::is_low(&self))
+    #[inline]
+    pub fn set_state(&mut self, state: PinState) {
+        match state {
+            PinState::Low => self._set_low(),
+            PinState::High => self._set_high(),
+        }
+    }
+
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        !self._is_set_low()
+    }
+
+    #[inline]
+    pub fn is_set_low(&self) -> bool {
+        self._is_set_low()
+    }
+
+    #[inline]
+    pub fn toggle(&mut self) {
+        if self._is_set_low() {
+            self._set_high()
+        } else {
+            self._set_low()
+        }
     }
 }
-const fn _regs(port: &Port) -> *const pac::gpioa::RegisterBlock {
-    match port {
-        Port::GPIOA => pac::GPIOA::ptr(),
-        Port::GPIOB => pac::GPIOB::ptr(),
-        Port::GPIOC => pac::GPIOC::ptr(),
-        Port::GPIOD => pac::GPIOD::ptr(),
+
+impl<const P: char, const N: u8, PULL> GpioPin<P, N, Input<PULL>> {
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        !self._is_low()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self._is_low()
+    }
+
+    /// Route EXTI line `N` to this pin and run `handler` from
+    /// [`exti::dispatch`] on every `trigger` edge
+    pub fn on_interrupt(&mut self, trigger: IntTrigger, handler: exti::Handler) {
+        exti::select_port(N, Self::port());
+        exti::set_trigger(N, trigger);
+        exti::clear_pending(N);
+        exti::register(N, handler);
+        exti::listen(N);
+    }
+
+    /// Mask EXTI line `N` and drop its handler
+    pub fn clear_interrupt(&mut self) {
+        exti::unlisten(N);
+        exti::clear_trigger(N);
+        exti::unregister(N);
+    }
+}
+
+impl<const P: char, const N: u8> GpioPin<P, N, Output<OpenDrain>> {
+    /// Level seen on the pin, which may be pulled low externally
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        !self._is_low()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self._is_low()
+    }
+}
+
+impl<const P: char, const N: u8, MODE> GpioPin<P, N, Locked<MODE>> {
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        !self._is_low()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self._is_low()
+    }
+}
+
+impl<const P: char, const N: u8, OTYPE> GpioPin<P, N, Locked<Output<OTYPE>>> {
+    #[inline]
+    pub fn set_high(&mut self) {
+        self._set_high()
+    }
+
+    #[inline]
+    pub fn set_low(&mut self) {
+        self._set_low()
+    }
+
+    #[inline]
+    pub fn set_state(&mut self, state: PinState) {
+        match state {
+            PinState::Low => self._set_low(),
+            PinState::High => self._set_high(),
+        }
+    }
+
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        !self._is_set_low()
+    }
+
+    #[inline]
+    pub fn is_set_low(&self) -> bool {
+        self._is_set_low()
+    }
+
+    #[inline]
+    pub fn toggle(&mut self) {
+        if self._is_set_low() {
+            self._set_high()
+        } else {
+            self._set_low()
+        }
+    }
+}
+
+/// Pin, or tuple of pins of one port, whose configuration can be locked
+///
+/// All pins are locked by a single key sequence, as LCKR accepts only one per
+/// port and reset. On failure the pins are handed back unchanged.
+pub trait Lockable: Sized {
+    type Locked;
+
+    fn lock(self) -> Result<Self::Locked, (Self, LockError)>;
+}
+
+impl<const P: char, const N: u8, MODE> Lockable for GpioPin<P, N, MODE> {
+    type Locked = GpioPin<P, N, Locked<MODE>>;
+
+    fn lock(self) -> Result<Self::Locked, (Self, LockError)> {
+        match lock_pins(Self::port(), 1 << N) {
+            Ok(()) => Ok(GpioPin::new()),
+            Err(e) => Err((self, e)),
+        }
+    }
+}
+
+macro_rules! lockable {
+    ($(($($N:ident: $MODE:ident),+),)+) => {
+        $(
+            impl<const P: char, $(const $N: u8, $MODE),+> Lockable for ($(GpioPin<P, $N, $MODE>,)+) {
+                type Locked = ($(GpioPin<P, $N, Locked<$MODE>>,)+);
+
+                fn lock(self) -> Result<Self::Locked, (Self, LockError)> {
+                    let mask = 0 $(| (1 << $N))+;
+                    match lock_pins(GpioPin::<P, 0>::port(), mask) {
+                        Ok(()) => Ok(($(GpioPin::<P, $N, Locked<$MODE>>::new(),)+)),
+                        Err(e) => Err((self, e)),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+lockable! {
+    (N1: M1, N2: M2),
+    (N1: M1, N2: M2, N3: M3),
+    (N1: M1, N2: M2, N3: M3, N4: M4),
+    (N1: M1, N2: M2, N3: M3, N4: M4, N5: M5),
+    (N1: M1, N2: M2, N3: M3, N4: M4, N5: M5, N6: M6),
+    (N1: M1, N2: M2, N3: M3, N4: M4, N5: M5, N6: M6, N7: M7),
+    (N1: M1, N2: M2, N3: M3, N4: M4, N5: M5, N6: M6, N7: M7, N8: M8),
+}
+
+macro_rules! gpio {
+    ($GPIOX:ident, $gpiox:ident, $P:literal, [$($PXi:ident: ($pxi:ident, $i:literal),)+]) => {
+        /// GPIO
+        pub mod $gpiox {
+            use super::{pac, Enable, Floating, GpioExt, GpioPin, Input, PortWriter, Reset};
+
+            /// Pins of the port, all floating inputs after `split`
+            pub struct Parts {
+                /// Whole-port access, see [`PortWriter`]
+                pub port: PortWriter<$P>,
+                $(
+                    pub $pxi: $PXi,
+                )+
+            }
+
+            $(
+                pub type $PXi<MODE = Input<Floating>> = GpioPin<$P, $i, MODE>;
+            )+
+
+            impl GpioExt for pac::$GPIOX {
+                type Parts = Parts;
+
+                fn split(self) -> Parts {
+                    pac::$GPIOX::enable();
+                    pac::$GPIOX::reset();
+
+                    Parts {
+                        port: PortWriter::new(),
+                        $(
+                            $pxi: GpioPin::new(),
+                        )+
+                    }
+                }
+            }
+        }
+
+        pub use $gpiox::{$($PXi),+};
+    };
+}
+
+gpio!(GPIOA, gpioa, 'A', [
+    PA0: (pa0, 0),
+    PA1: (pa1, 1),
+    PA2: (pa2, 2),
+    PA3: (pa3, 3),
+    PA4: (pa4, 4),
+    PA5: (pa5, 5),
+    PA6: (pa6, 6),
+    PA7: (pa7, 7),
+    PA8: (pa8, 8),
+    PA9: (pa9, 9),
+    PA10: (pa10, 10),
+    PA11: (pa11, 11),
+    PA12: (pa12, 12),
+    PA13: (pa13, 13),
+    PA14: (pa14, 14),
+    PA15: (pa15, 15),
+]);
+
+gpio!(GPIOB, gpiob, 'B', [
+    PB0: (pb0, 0),
+    PB1: (pb1, 1),
+    PB2: (pb2, 2),
+    PB3: (pb3, 3),
+    PB4: (pb4, 4),
+    PB5: (pb5, 5),
+    PB6: (pb6, 6),
+    PB7: (pb7, 7),
+    PB8: (pb8, 8),
+    PB9: (pb9, 9),
+    PB10: (pb10, 10),
+    PB11: (pb11, 11),
+    PB12: (pb12, 12),
+    PB13: (pb13, 13),
+    PB14: (pb14, 14),
+    PB15: (pb15, 15),
+]);
+
+gpio!(GPIOC, gpioc, 'C', [
+    PC0: (pc0, 0),
+    PC1: (pc1, 1),
+    PC2: (pc2, 2),
+    PC3: (pc3, 3),
+    PC4: (pc4, 4),
+    PC5: (pc5, 5),
+    PC6: (pc6, 6),
+    PC7: (pc7, 7),
+    PC8: (pc8, 8),
+    PC9: (pc9, 9),
+    PC10: (pc10, 10),
+    PC11: (pc11, 11),
+    PC12: (pc12, 12),
+    PC13: (pc13, 13),
+    PC14: (pc14, 14),
+    PC15: (pc15, 15),
+]);
+
+gpio!(GPIOD, gpiod, 'D', [
+    PD0: (pd0, 0),
+    PD1: (pd1, 1),
+    PD2: (pd2, 2),
+]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [PinConfig; 16] = [
+        PinConfig::FloatingInput,
+        PinConfig::PullUpInput,
+        PinConfig::PullDownInput,
+        PinConfig::Analog,
+        PinConfig::Output(OutputType::PushPull, OutputSpeed::LowSpeed),
+        PinConfig::Output(OutputType::PushPull, OutputSpeed::MediumSpeed),
+        PinConfig::Output(OutputType::PushPull, OutputSpeed::HighSpeed),
+        PinConfig::Output(OutputType::OpenDrain, OutputSpeed::LowSpeed),
+        PinConfig::Output(OutputType::OpenDrain, OutputSpeed::MediumSpeed),
+        PinConfig::Output(OutputType::OpenDrain, OutputSpeed::HighSpeed),
+        PinConfig::Alternate(OutputType::PushPull, OutputSpeed::LowSpeed),
+        PinConfig::Alternate(OutputType::PushPull, OutputSpeed::MediumSpeed),
+        PinConfig::Alternate(OutputType::PushPull, OutputSpeed::HighSpeed),
+        PinConfig::Alternate(OutputType::OpenDrain, OutputSpeed::LowSpeed),
+        PinConfig::Alternate(OutputType::OpenDrain, OutputSpeed::MediumSpeed),
+        PinConfig::Alternate(OutputType::OpenDrain, OutputSpeed::HighSpeed),
+    ];
+
+    #[test]
+    fn config_nibbles_match_reference_manual() {
+        assert_eq!(PinConfig::Analog.bits(), 0b0000);
+        assert_eq!(PinConfig::FloatingInput.bits(), 0b0100);
+        assert_eq!(PinConfig::PullUpInput.bits(), 0b1000);
+        assert_eq!(PinConfig::PullDownInput.bits(), 0b1000);
+        assert_eq!(PinConfig::Output(OutputType::PushPull, OutputSpeed::MediumSpeed).bits(), 0b0001);
+        assert_eq!(PinConfig::Output(OutputType::PushPull, OutputSpeed::LowSpeed).bits(), 0b0010);
+        assert_eq!(PinConfig::Output(OutputType::OpenDrain, OutputSpeed::HighSpeed).bits(), 0b0111);
+        assert_eq!(PinConfig::Alternate(OutputType::PushPull, OutputSpeed::HighSpeed).bits(), 0b1011);
+        assert_eq!(PinConfig::Alternate(OutputType::OpenDrain, OutputSpeed::LowSpeed).bits(), 0b1110);
+    }
+
+    #[test]
+    fn config_round_trips_through_bits() {
+        for cfg in ALL {
+            let odr = cfg == PinConfig::PullUpInput;
+            assert_eq!(PinConfig::from_bits(cfg.bits(), odr), cfg);
+        }
+    }
+
+    #[test]
+    fn cfgr_value_replaces_only_the_pin_nibble() {
+        for pin in 0..16u8 {
+            let offset = 4 * (pin as u32 % 8);
+            for cfg in ALL {
+                for before in [0x0000_0000, 0xffff_ffff, 0x4444_4444, 0x1234_5678] {
+                    let after = cfgr_value(before, pin, cfg.bits());
+                    assert_eq!((after >> offset) & 0x0f, cfg.bits());
+                    assert_eq!(after & !(0x0f << offset), before & !(0x0f << offset));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bshr_value_sets_and_resets_single_pin() {
+        for pin in 0..16u8 {
+            assert_eq!(bshr_value(pin, PinState::High), 1 << pin);
+            assert_eq!(bshr_value(pin, PinState::Low), 1 << (pin + 16));
+        }
     }
 }
@@ -0,0 +1,145 @@
+//! Alternate function pin traits
+//!
+//! Each trait is implemented for exactly the pins, in the right mode, that can
+//! carry a signal of peripheral `PER` under the AFIO mapping `REMAP`. A driver
+//! bounds its pins on these traits and calls [`Remap::apply`](crate::afio::Remap::apply) once, e.g.
+//!
+//! ```ignore
+//! fn new<TX, RX, R>(usart: USART1, pins: (TX, RX)) -> Self
+//! where
+//!     TX: TxPin<USART1, R>,
+//!     RX: RxPin<USART1, R>,
+//!     R: Remap<USART1>,
+//! ```
+//!
+//! so a pin that cannot be routed to the peripheral is a compile error.
+
+use super::*;
+use crate::afio::{FullRemap, NoRemap, PartialRemap, PartialRemap1, PartialRemap2};
+
+/// USART transmit pin
+pub trait TxPin<USART, REMAP = NoRemap> {}
+/// USART receive pin
+pub trait RxPin<USART, REMAP = NoRemap> {}
+
+/// SPI clock pin
+pub trait SckPin<SPI, REMAP = NoRemap> {}
+/// SPI master in, slave out pin
+pub trait MisoPin<SPI, REMAP = NoRemap> {}
+/// SPI master out, slave in pin
+pub trait MosiPin<SPI, REMAP = NoRemap> {}
+/// SPI slave select pin
+pub trait NssPin<SPI, REMAP = NoRemap> {}
+
+/// I2C clock pin
+pub trait SclPin<I2C, REMAP = NoRemap> {}
+/// I2C data pin
+pub trait SdaPin<I2C, REMAP = NoRemap> {}
+
+/// Timer channel 1 pin
+pub trait Ch1<TIM, REMAP = NoRemap> {}
+/// Timer channel 2 pin
+pub trait Ch2<TIM, REMAP = NoRemap> {}
+/// Timer channel 3 pin
+pub trait Ch3<TIM, REMAP = NoRemap> {}
+/// Timer channel 4 pin
+pub trait Ch4<TIM, REMAP = NoRemap> {}
+
+macro_rules! pins {
+    ($($Trait:ident<$PER:ident, $REMAP:ident>: [$($PIN:ident<$MODE:ty>),+];)+) => {
+        $($(
+            impl $Trait<pac::$PER, $REMAP> for $PIN<$MODE> {}
+        )+)+
+    };
+}
+
+type AfPushPull = Alternate<PushPull>;
+type AfOpenDrain = Alternate<OpenDrain>;
+
+pins! {
+    TxPin<USART1, NoRemap>: [PA9<AfPushPull>];
+    RxPin<USART1, NoRemap>: [PA10<Input<Floating>>, PA10<Input<PullUp>>];
+    TxPin<USART1, FullRemap>: [PB6<AfPushPull>];
+    RxPin<USART1, FullRemap>: [PB7<Input<Floating>>, PB7<Input<PullUp>>];
+
+    TxPin<USART2, NoRemap>: [PA2<AfPushPull>];
+    RxPin<USART2, NoRemap>: [PA3<Input<Floating>>, PA3<Input<PullUp>>];
+
+    TxPin<USART3, NoRemap>: [PB10<AfPushPull>];
+    RxPin<USART3, NoRemap>: [PB11<Input<Floating>>, PB11<Input<PullUp>>];
+    TxPin<USART3, PartialRemap>: [PC10<AfPushPull>];
+    RxPin<USART3, PartialRemap>: [PC11<Input<Floating>>, PC11<Input<PullUp>>];
+}
+
+pins! {
+    NssPin<SPI1, NoRemap>: [PA4<AfPushPull>, PA4<Input<Floating>>];
+    SckPin<SPI1, NoRemap>: [PA5<AfPushPull>];
+    MisoPin<SPI1, NoRemap>: [PA6<Input<Floating>>, PA6<Input<PullUp>>];
+    MosiPin<SPI1, NoRemap>: [PA7<AfPushPull>];
+    NssPin<SPI1, FullRemap>: [PA15<AfPushPull>, PA15<Input<Floating>>];
+    SckPin<SPI1, FullRemap>: [PB3<AfPushPull>];
+    MisoPin<SPI1, FullRemap>: [PB4<Input<Floating>>, PB4<Input<PullUp>>];
+    MosiPin<SPI1, FullRemap>: [PB5<AfPushPull>];
+
+    NssPin<SPI2, NoRemap>: [PB12<AfPushPull>, PB12<Input<Floating>>];
+    SckPin<SPI2, NoRemap>: [PB13<AfPushPull>];
+    MisoPin<SPI2, NoRemap>: [PB14<Input<Floating>>, PB14<Input<PullUp>>];
+    MosiPin<SPI2, NoRemap>: [PB15<AfPushPull>];
+}
+
+pins! {
+    SclPin<I2C1, NoRemap>: [PB6<AfOpenDrain>];
+    SdaPin<I2C1, NoRemap>: [PB7<AfOpenDrain>];
+    SclPin<I2C1, FullRemap>: [PB8<AfOpenDrain>];
+    SdaPin<I2C1, FullRemap>: [PB9<AfOpenDrain>];
+
+    SclPin<I2C2, NoRemap>: [PB10<AfOpenDrain>];
+    SdaPin<I2C2, NoRemap>: [PB11<AfOpenDrain>];
+}
+
+// TIM1 partial remap only moves ETR, BKIN and the complementary outputs
+pins! {
+    Ch1<TIM1, NoRemap>: [PA8<AfPushPull>];
+    Ch2<TIM1, NoRemap>: [PA9<AfPushPull>];
+    Ch3<TIM1, NoRemap>: [PA10<AfPushPull>];
+    Ch4<TIM1, NoRemap>: [PA11<AfPushPull>];
+    Ch1<TIM1, PartialRemap>: [PA8<AfPushPull>];
+    Ch2<TIM1, PartialRemap>: [PA9<AfPushPull>];
+    Ch3<TIM1, PartialRemap>: [PA10<AfPushPull>];
+    Ch4<TIM1, PartialRemap>: [PA11<AfPushPull>];
+
+    Ch1<TIM2, NoRemap>: [PA0<AfPushPull>];
+    Ch2<TIM2, NoRemap>: [PA1<AfPushPull>];
+    Ch3<TIM2, NoRemap>: [PA2<AfPushPull>];
+    Ch4<TIM2, NoRemap>: [PA3<AfPushPull>];
+    Ch1<TIM2, PartialRemap1>: [PA15<AfPushPull>];
+    Ch2<TIM2, PartialRemap1>: [PB3<AfPushPull>];
+    Ch3<TIM2, PartialRemap1>: [PA2<AfPushPull>];
+    Ch4<TIM2, PartialRemap1>: [PA3<AfPushPull>];
+    Ch1<TIM2, PartialRemap2>: [PA0<AfPushPull>];
+    Ch2<TIM2, PartialRemap2>: [PA1<AfPushPull>];
+    Ch3<TIM2, PartialRemap2>: [PB10<AfPushPull>];
+    Ch4<TIM2, PartialRemap2>: [PB11<AfPushPull>];
+    Ch1<TIM2, FullRemap>: [PA15<AfPushPull>];
+    Ch2<TIM2, FullRemap>: [PB3<AfPushPull>];
+    Ch3<TIM2, FullRemap>: [PB10<AfPushPull>];
+    Ch4<TIM2, FullRemap>: [PB11<AfPushPull>];
+
+    Ch1<TIM3, NoRemap>: [PA6<AfPushPull>];
+    Ch2<TIM3, NoRemap>: [PA7<AfPushPull>];
+    Ch3<TIM3, NoRemap>: [PB0<AfPushPull>];
+    Ch4<TIM3, NoRemap>: [PB1<AfPushPull>];
+    Ch1<TIM3, PartialRemap>: [PB4<AfPushPull>];
+    Ch2<TIM3, PartialRemap>: [PB5<AfPushPull>];
+    Ch3<TIM3, PartialRemap>: [PB0<AfPushPull>];
+    Ch4<TIM3, PartialRemap>: [PB1<AfPushPull>];
+    Ch1<TIM3, FullRemap>: [PC6<AfPushPull>];
+    Ch2<TIM3, FullRemap>: [PC7<AfPushPull>];
+    Ch3<TIM3, FullRemap>: [PC8<AfPushPull>];
+    Ch4<TIM3, FullRemap>: [PC9<AfPushPull>];
+
+    Ch1<TIM4, NoRemap>: [PB6<AfPushPull>];
+    Ch2<TIM4, NoRemap>: [PB7<AfPushPull>];
+    Ch3<TIM4, NoRemap>: [PB8<AfPushPull>];
+    Ch4<TIM4, NoRemap>: [PB9<AfPushPull>];
+}
@@ -0,0 +1,166 @@
+//! Pin whose mode changes at runtime
+//!
+//! For bidirectional protocols such as 1-Wire or bit-banged I2C, where the
+//! same pin is driven and read in turn without going through ownership
+//! conversions.
+
+use super::*;
+
+/// Operation not available in the current mode of a [`DynamicPin`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinModeError {
+    /// Read while not an input, or written while not an output
+    IncorrectMode,
+}
+
+/// Pin whose CFGxR nibble is switched on the fly, tracking its current mode
+pub struct DynamicPin<const P: char, const N: u8> {
+    cfg: PinConfig,
+}
+
+impl<const P: char, const N: u8, MODE: Mode> GpioPin<P, N, MODE> {
+    /// Hand the mode over to runtime, starting from the current one
+    pub fn into_dynamic(self) -> DynamicPin<P, N> {
+        DynamicPin { cfg: MODE::CFG }
+    }
+}
+
+impl<const P: char, const N: u8> DynamicPin<P, N> {
+    #[inline(always)]
+    fn raw(&self) -> Pin {
+        GpioPin::<P, N>::new().raw()
+    }
+
+    fn set_config(&mut self, cfg: PinConfig) {
+        self.raw().configure(cfg);
+        self.cfg = cfg;
+    }
+
+    /// Current mode
+    #[inline]
+    pub fn config(&self) -> PinConfig {
+        self.cfg
+    }
+
+    /// Switch between input and output through [`Pin::mode`], keeping the
+    /// rest of the configuration when the pin already is in that mode
+    pub fn set_mode(&mut self, mode: PinMode) {
+        let raw = self.raw();
+        raw.mode(mode);
+        self.cfg = raw.config();
+    }
+
+    pub fn make_floating_input(&mut self) {
+        self.set_config(PinConfig::FloatingInput)
+    }
+
+    pub fn make_pull_up_input(&mut self) {
+        self.set_config(PinConfig::PullUpInput)
+    }
+
+    pub fn make_pull_down_input(&mut self) {
+        self.set_config(PinConfig::PullDownInput)
+    }
+
+    pub fn make_push_pull_output(&mut self) {
+        self.set_config(PinConfig::Output(OutputType::PushPull, OutputSpeed::LowSpeed))
+    }
+
+    pub fn make_open_drain_output(&mut self) {
+        self.set_config(PinConfig::Output(OutputType::OpenDrain, OutputSpeed::LowSpeed))
+    }
+
+    fn is_input(&self) -> bool {
+        matches!(
+            self.cfg,
+            PinConfig::FloatingInput | PinConfig::PullUpInput | PinConfig::PullDownInput
+        )
+    }
+
+    fn is_output(&self) -> bool {
+        matches!(self.cfg, PinConfig::Output(..))
+    }
+
+    pub fn set_high(&mut self) -> Result<(), PinModeError> {
+        self.set_state(PinState::High)
+    }
+
+    pub fn set_low(&mut self) -> Result<(), PinModeError> {
+        self.set_state(PinState::Low)
+    }
+
+    pub fn set_state(&mut self, state: PinState) -> Result<(), PinModeError> {
+        if !self.is_output() {
+            return Err(PinModeError::IncorrectMode);
+        }
+        self.raw().set_state(state);
+        Ok(())
+    }
+
+    pub fn is_high(&self) -> Result<bool, PinModeError> {
+        self.is_low().map(|low| !low)
+    }
+
+    pub fn is_low(&self) -> Result<bool, PinModeError> {
+        if !self.is_input() {
+            return Err(PinModeError::IncorrectMode);
+        }
+        Ok(self.raw().is_low())
+    }
+}
+
+impl embedded_hal::digital::Error for PinModeError {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl<const P: char, const N: u8> embedded_hal::digital::ErrorType for DynamicPin<P, N> {
+    type Error = PinModeError;
+}
+
+impl<const P: char, const N: u8> embedded_hal::digital::InputPin for DynamicPin<P, N> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        DynamicPin::is_high(self)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        DynamicPin::is_low(self)
+    }
+}
+
+impl<const P: char, const N: u8> embedded_hal::digital::OutputPin for DynamicPin<P, N> {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        DynamicPin::set_high(self)
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        DynamicPin::set_low(self)
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl<const P: char, const N: u8> embedded_hal_02::digital::v2::InputPin for DynamicPin<P, N> {
+    type Error = PinModeError;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        DynamicPin::is_high(self)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        DynamicPin::is_low(self)
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl<const P: char, const N: u8> embedded_hal_02::digital::v2::OutputPin for DynamicPin<P, N> {
+    type Error = PinModeError;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        DynamicPin::set_high(self)
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        DynamicPin::set_low(self)
+    }
+}
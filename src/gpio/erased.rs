@@ -0,0 +1,205 @@
+//! Pins with the number, or number and port, moved to runtime
+//!
+//! Erasing keeps the mode in the type, so an erased input still has no
+//! `set_high`, but pins of different numbers or ports share one type and can
+//! be stored in an array.
+
+use super::*;
+
+/// Pin of port `P` whose number is only known at runtime
+pub struct PartiallyErasedPin<const P: char, MODE> {
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<const P: char, MODE> PartiallyErasedPin<P, MODE> {
+    pub(crate) const fn new(i: u8) -> Self {
+        Self { i, _mode: PhantomData }
+    }
+
+    /// Pin number
+    #[inline]
+    pub fn pin_id(&self) -> u8 {
+        self.i
+    }
+
+    /// Port letter
+    #[inline]
+    pub fn port_id(&self) -> char {
+        P
+    }
+
+    /// Move the port to runtime as well
+    pub fn erase(self) -> ErasedPin<MODE> {
+        ErasedPin::new(GpioPin::<P, 0>::port(), self.i)
+    }
+
+    #[inline(always)]
+    fn raw(&self) -> Pin {
+        Pin {
+            port: GpioPin::<P, 0>::port(),
+            pin: self.i,
+        }
+    }
+}
+
+/// Pin whose port and number are only known at runtime
+pub struct ErasedPin<MODE> {
+    port: Port,
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> ErasedPin<MODE> {
+    pub(crate) const fn new(port: Port, i: u8) -> Self {
+        Self {
+            port,
+            i,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Pin number
+    #[inline]
+    pub fn pin_id(&self) -> u8 {
+        self.i
+    }
+
+    /// Port of the pin
+    #[inline]
+    pub fn port_id(&self) -> Port {
+        self.port
+    }
+
+    #[inline(always)]
+    fn raw(&self) -> Pin {
+        Pin {
+            port: self.port,
+            pin: self.i,
+        }
+    }
+}
+
+impl<const P: char, const N: u8, MODE> GpioPin<P, N, MODE> {
+    /// Move the pin number to runtime, e.g. to keep pins of one port in an array
+    pub fn erase_number(self) -> PartiallyErasedPin<P, MODE> {
+        PartiallyErasedPin::new(N)
+    }
+
+    /// Move the port and pin number to runtime
+    pub fn erase(self) -> ErasedPin<MODE> {
+        ErasedPin::new(Self::port(), N)
+    }
+}
+
+impl<const P: char, const N: u8, MODE> From<GpioPin<P, N, MODE>> for PartiallyErasedPin<P, MODE> {
+    fn from(pin: GpioPin<P, N, MODE>) -> Self {
+        pin.erase_number()
+    }
+}
+
+impl<const P: char, const N: u8, MODE> From<GpioPin<P, N, MODE>> for ErasedPin<MODE> {
+    fn from(pin: GpioPin<P, N, MODE>) -> Self {
+        pin.erase()
+    }
+}
+
+impl<const P: char, MODE> From<PartiallyErasedPin<P, MODE>> for ErasedPin<MODE> {
+    fn from(pin: PartiallyErasedPin<P, MODE>) -> Self {
+        pin.erase()
+    }
+}
+
+macro_rules! erased {
+    ($([$($gen:tt)*] $Pin:ident<$($arg:ident),*>,)+) => {
+        $(
+            impl<$($gen)* MODE> $Pin<$($arg,)* MODE> {
+                #[inline(always)]
+                pub(super) fn _set_high(&self) {
+                    self.raw().set_state(PinState::High)
+                }
+
+                #[inline(always)]
+                pub(super) fn _set_low(&self) {
+                    self.raw().set_state(PinState::Low)
+                }
+
+                #[inline(always)]
+                pub(super) fn _is_set_low(&self) -> bool {
+                    self.raw().is_set_low()
+                }
+
+                #[inline(always)]
+                pub(super) fn _is_low(&self) -> bool {
+                    self.raw().is_low()
+                }
+            }
+
+            impl<$($gen)* OTYPE> $Pin<$($arg,)* Output<OTYPE>> {
+                #[inline]
+                pub fn set_high(&mut self) {
+                    self._set_high()
+                }
+
+                #[inline]
+                pub fn set_low(&mut self) {
+                    self._set_low()
+                }
+
+                #[inline]
+                pub fn set_state(&mut self, state: PinState) {
+                    self.raw().set_state(state)
+                }
+
+                #[inline]
+                pub fn is_set_high(&self) -> bool {
+                    !self._is_set_low()
+                }
+
+                #[inline]
+                pub fn is_set_low(&self) -> bool {
+                    self._is_set_low()
+                }
+
+                #[inline]
+                pub fn toggle(&mut self) {
+                    if self._is_set_low() {
+                        self._set_high()
+                    } else {
+                        self._set_low()
+                    }
+                }
+            }
+
+            impl<$($gen)* PULL> $Pin<$($arg,)* Input<PULL>> {
+                #[inline]
+                pub fn is_high(&self) -> bool {
+                    !self._is_low()
+                }
+
+                #[inline]
+                pub fn is_low(&self) -> bool {
+                    self._is_low()
+                }
+            }
+
+            impl<$($gen)*> $Pin<$($arg,)* Output<OpenDrain>> {
+                /// Level seen on the pin, which may be pulled low externally
+                #[inline]
+                pub fn is_high(&self) -> bool {
+                    !self._is_low()
+                }
+
+                #[inline]
+                pub fn is_low(&self) -> bool {
+                    self._is_low()
+                }
+            }
+        )+
+    };
+}
+
+erased! {
+    [const P: char,] PartiallyErasedPin<P>,
+    [] ErasedPin<>,
+}
@@ -0,0 +1,137 @@
+//! embedded-hal 0.2 digital traits, behind the `embedded-hal-02` feature
+
+use super::*;
+use embedded_hal_02::digital::v2::{
+    InputPin, OutputPin, PinState as PinStateV2, StatefulOutputPin, ToggleableOutputPin,
+};
+
+impl InputPin for Pin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_high(self))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_low(self))
+    }
+}
+
+impl OutputPin for Pin {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Pin::set_high(self);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Pin::set_low(self);
+        Ok(())
+    }
+
+    fn set_state(&mut self, state: PinStateV2) -> Result<(), Self::Error> {
+        match state {
+            PinStateV2::High => Pin::set_state(self, PinState::High),
+            PinStateV2::Low => Pin::set_state(self, PinState::Low),
+        }
+        Ok(())
+    }
+}
+
+impl ToggleableOutputPin for Pin {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        Pin::toggle(self);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Pin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(!Pin::is_set_low(self))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_low(self))
+    }
+}
+
+macro_rules! input_pin {
+    ($([$($gen:tt)*] $Pin:ty,)+) => {
+        $(
+            impl<$($gen)*> InputPin for $Pin {
+                type Error = Infallible;
+
+                fn is_high(&self) -> Result<bool, Self::Error> {
+                    Ok(!self._is_low())
+                }
+
+                fn is_low(&self) -> Result<bool, Self::Error> {
+                    Ok(self._is_low())
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! output_pin {
+    ($([$($gen:tt)*] $Pin:ty,)+) => {
+        $(
+            impl<$($gen)*> OutputPin for $Pin {
+                type Error = Infallible;
+
+                fn set_high(&mut self) -> Result<(), Self::Error> {
+                    self._set_high();
+                    Ok(())
+                }
+
+                fn set_low(&mut self) -> Result<(), Self::Error> {
+                    self._set_low();
+                    Ok(())
+                }
+            }
+
+            impl<$($gen)*> StatefulOutputPin for $Pin {
+                fn is_set_high(&self) -> Result<bool, Self::Error> {
+                    Ok(!self._is_set_low())
+                }
+
+                fn is_set_low(&self) -> Result<bool, Self::Error> {
+                    Ok(self._is_set_low())
+                }
+            }
+
+            impl<$($gen)*> ToggleableOutputPin for $Pin {
+                type Error = Infallible;
+
+                fn toggle(&mut self) -> Result<(), Self::Error> {
+                    if self._is_set_low() {
+                        self._set_high()
+                    } else {
+                        self._set_low()
+                    }
+                    Ok(())
+                }
+            }
+        )+
+    };
+}
+
+input_pin! {
+    [const P: char, const N: u8, PULL] GpioPin<P, N, Input<PULL>>,
+    [const P: char, const N: u8] GpioPin<P, N, Output<OpenDrain>>,
+    [const P: char, const N: u8, MODE] GpioPin<P, N, Locked<MODE>>,
+    [const P: char, PULL] PartiallyErasedPin<P, Input<PULL>>,
+    [const P: char] PartiallyErasedPin<P, Output<OpenDrain>>,
+    [PULL] ErasedPin<Input<PULL>>,
+    [] ErasedPin<Output<OpenDrain>>,
+}
+
+output_pin! {
+    [const P: char, const N: u8, OTYPE] GpioPin<P, N, Output<OTYPE>>,
+    [const P: char, const N: u8, OTYPE] GpioPin<P, N, Locked<Output<OTYPE>>>,
+    [const P: char, OTYPE] PartiallyErasedPin<P, Output<OTYPE>>,
+    [OTYPE] ErasedPin<Output<OTYPE>>,
+}
@@ -0,0 +1,118 @@
+//! embedded-hal 1.0 digital traits
+
+use super::*;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, StatefulOutputPin};
+
+impl ErrorType for Pin {
+    type Error = Infallible;
+}
+
+impl InputPin for Pin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_high(self))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_low(self))
+    }
+}
+
+impl OutputPin for Pin {
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Pin::set_high(self);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Pin::set_low(self);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Pin {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(!Pin::is_set_low(self))
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_low(self))
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        Pin::toggle(self);
+        Ok(())
+    }
+}
+
+impl<const P: char, const N: u8, MODE> ErrorType for GpioPin<P, N, MODE> {
+    type Error = Infallible;
+}
+
+impl<const P: char, MODE> ErrorType for PartiallyErasedPin<P, MODE> {
+    type Error = Infallible;
+}
+
+impl<MODE> ErrorType for ErasedPin<MODE> {
+    type Error = Infallible;
+}
+
+macro_rules! input_pin {
+    ($([$($gen:tt)*] $Pin:ty,)+) => {
+        $(
+            impl<$($gen)*> InputPin for $Pin {
+                fn is_high(&mut self) -> Result<bool, Self::Error> {
+                    Ok(!self._is_low())
+                }
+
+                fn is_low(&mut self) -> Result<bool, Self::Error> {
+                    Ok(self._is_low())
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! output_pin {
+    ($([$($gen:tt)*] $Pin:ty,)+) => {
+        $(
+            impl<$($gen)*> OutputPin for $Pin {
+                fn set_high(&mut self) -> Result<(), Self::Error> {
+                    self._set_high();
+                    Ok(())
+                }
+
+                fn set_low(&mut self) -> Result<(), Self::Error> {
+                    self._set_low();
+                    Ok(())
+                }
+            }
+
+            impl<$($gen)*> StatefulOutputPin for $Pin {
+                fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+                    Ok(!self._is_set_low())
+                }
+
+                fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+                    Ok(self._is_set_low())
+                }
+            }
+        )+
+    };
+}
+
+input_pin! {
+    [const P: char, const N: u8, PULL] GpioPin<P, N, Input<PULL>>,
+    [const P: char, const N: u8] GpioPin<P, N, Output<OpenDrain>>,
+    [const P: char, const N: u8, MODE] GpioPin<P, N, Locked<MODE>>,
+    [const P: char, PULL] PartiallyErasedPin<P, Input<PULL>>,
+    [const P: char] PartiallyErasedPin<P, Output<OpenDrain>>,
+    [PULL] ErasedPin<Input<PULL>>,
+    [] ErasedPin<Output<OpenDrain>>,
+}
+
+output_pin! {
+    [const P: char, const N: u8, OTYPE] GpioPin<P, N, Output<OTYPE>>,
+    [const P: char, const N: u8, OTYPE] GpioPin<P, N, Locked<Output<OTYPE>>>,
+    [const P: char, OTYPE] PartiallyErasedPin<P, Output<OTYPE>>,
+    [OTYPE] ErasedPin<Output<OTYPE>>,
+}
@@ -0,0 +1,238 @@
+//! Whole-port and multi-pin access
+//!
+//! Both go through a single BSHR store, so every pin of a write changes in the
+//! same cycle and no critical section is needed.
+
+use super::*;
+
+/// BSHR value driving the pins in `mask` to the matching bits of `value`,
+/// pins outside `mask` are left alone
+pub(crate) const fn bshr_masked(mask: u16, value: u16) -> u32 {
+    let set = value & mask;
+    let reset = !value & mask;
+    ((reset as u32) << 16) | set as u32
+}
+
+/// Port mask of `pins`
+const fn group_mask(pins: &[u8]) -> u16 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < pins.len() {
+        mask |= 1 << pins[i];
+        i += 1;
+    }
+    mask
+}
+
+/// Pins configured as outputs, MODE is the low 2 bits of each CFGxR nibble
+const fn output_pins(cfglr: u32, cfghr: u32) -> u16 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < 8 {
+        if (cfglr >> (i * 4)) & 0b11 != 0 {
+            mask |= 1 << i;
+        }
+        if (cfghr >> (i * 4)) & 0b11 != 0 {
+            mask |= 1 << (i + 8);
+        }
+        i += 1;
+    }
+    mask
+}
+
+/// Spread the bitfield `value` onto `pins`, bit `i` goes to `pins[i]`
+const fn group_spread(pins: &[u8], value: u16) -> u16 {
+    let mut out = 0;
+    let mut i = 0;
+    while i < pins.len() {
+        if value & (1 << i) != 0 {
+            out |= 1 << pins[i];
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Inverse of [`group_spread`]
+const fn group_gather(pins: &[u8], port: u16) -> u16 {
+    let mut out = 0;
+    let mut i = 0;
+    while i < pins.len() {
+        if port & (1 << pins[i]) != 0 {
+            out |= 1 << i;
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Whole-port access to GPIO port `P`, handed out by [`GpioExt::split`]
+///
+/// On inputs with a pull resistor OUTDR selects pull-up or pull-down, so
+/// writes are restricted to the pins configured as outputs when they are
+/// made. The writer does not own the pins, masking out outputs used
+/// elsewhere is up to the caller.
+pub struct PortWriter<const P: char> {
+    _private: (),
+}
+
+impl<const P: char> PortWriter<P> {
+    pub(crate) const fn new() -> Self {
+        Self { _private: () }
+    }
+
+    #[inline(always)]
+    fn regs(&self) -> &pac::gpioa::RegisterBlock {
+        unsafe { &(*_regs(&GpioPin::<P, 0>::port())) }
+    }
+
+    /// Drive the output pins in `mask` to the matching bits of `value` in one
+    /// store, inputs in `mask` keep their pull
+    #[inline]
+    pub fn write(&mut self, mask: u16, value: u16) {
+        let regs = self.regs();
+        let outputs = output_pins(regs.cfglr.read().bits(), regs.cfghr.read().bits());
+        regs.bshr.write(|w| unsafe { w.bits(bshr_masked(mask & outputs, value)) })
+    }
+
+    /// Drive the pins in `mask` high
+    #[inline]
+    pub fn set_high(&mut self, mask: u16) {
+        self.write(mask, 0xffff)
+    }
+
+    /// Drive the pins in `mask` low
+    #[inline]
+    pub fn set_low(&mut self, mask: u16) {
+        self.write(mask, 0)
+    }
+
+    /// Level of all pins of the port, from INDR
+    #[inline]
+    pub fn read(&self) -> u16 {
+        self.regs().indr.read().bits() as u16
+    }
+
+    /// Output data register of the port
+    #[inline]
+    pub fn read_output(&self) -> u16 {
+        self.regs().outdr.read().bits() as u16
+    }
+}
+
+/// Set of output pins on one port that can form a [`PinGroup`]
+///
+/// Implemented for tuples of up to 8 outputs of the same port, the first
+/// element is bit 0 of the group.
+pub trait PinSet {
+    #[doc(hidden)]
+    const PORT: Port;
+    #[doc(hidden)]
+    const PINS: &'static [u8];
+}
+
+macro_rules! pin_set {
+    ($(($($N:ident: $OTYPE:ident),+),)+) => {
+        $(
+            impl<const P: char, $(const $N: u8, $OTYPE),+> PinSet for ($(GpioPin<P, $N, Output<$OTYPE>>,)+) {
+                const PORT: Port = GpioPin::<P, 0>::port();
+                const PINS: &'static [u8] = &[$($N),+];
+            }
+        )+
+    };
+}
+
+pin_set! {
+    (N1: O1),
+    (N1: O1, N2: O2),
+    (N1: O1, N2: O2, N3: O3),
+    (N1: O1, N2: O2, N3: O3, N4: O4),
+    (N1: O1, N2: O2, N3: O3, N4: O4, N5: O5),
+    (N1: O1, N2: O2, N3: O3, N4: O4, N5: O5, N6: O6),
+    (N1: O1, N2: O2, N3: O3, N4: O4, N5: O5, N6: O6, N7: O7),
+    (N1: O1, N2: O2, N3: O3, N4: O4, N5: O5, N6: O6, N7: O7, N8: O8),
+}
+
+/// Output pins of one port written together as a bitfield
+///
+/// ```ignore
+/// let mut data = PinGroup::new((gpiob.pb8, gpiob.pb3, gpiob.pb12));
+/// data.write(0b101); // PB8 and PB12 high, PB3 low
+/// ```
+pub struct PinGroup<PINS: PinSet> {
+    pins: PINS,
+}
+
+impl<PINS: PinSet> PinGroup<PINS> {
+    const MASK: u16 = group_mask(PINS::PINS);
+
+    pub fn new(pins: PINS) -> Self {
+        Self { pins }
+    }
+
+    /// Give the pins back
+    pub fn release(self) -> PINS {
+        self.pins
+    }
+
+    #[inline(always)]
+    fn regs(&self) -> &pac::gpioa::RegisterBlock {
+        unsafe { &(*_regs(&PINS::PORT)) }
+    }
+
+    /// Number of pins in the group
+    pub const fn width(&self) -> usize {
+        PINS::PINS.len()
+    }
+
+    /// Drive bit `i` of `value` onto the `i`th pin, all pins change at once
+    #[inline]
+    pub fn write(&mut self, value: u16) {
+        let bits = bshr_masked(Self::MASK, group_spread(PINS::PINS, value));
+        self.regs().bshr.write(|w| unsafe { w.bits(bits) })
+    }
+
+    /// Last value written to the group
+    #[inline]
+    pub fn read_output(&self) -> u16 {
+        group_gather(PINS::PINS, self.regs().outdr.read().bits() as u16)
+    }
+
+    /// Level seen on the pins of the group
+    #[inline]
+    pub fn read(&self) -> u16 {
+        group_gather(PINS::PINS, self.regs().indr.read().bits() as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masked_write_touches_only_mask() {
+        assert_eq!(bshr_masked(0x00ff, 0xa5a5), 0x005a_00a5);
+        assert_eq!(bshr_masked(0, 0xffff), 0);
+        assert_eq!(bshr_masked(0xffff, 0), 0xffff_0000);
+    }
+
+    #[test]
+    fn output_pins_from_mode_bits() {
+        // reset value, every pin a floating input
+        assert_eq!(output_pins(0x4444_4444, 0x4444_4444), 0);
+        // PA0 push-pull 2 MHz, PA3 pulled input, PA9 open-drain 50 MHz
+        assert_eq!(output_pins(0x4444_8442, 0x4444_4474), 0x0201);
+        assert_eq!(output_pins(0x1111_1111, 0x3333_3333), 0xffff);
+    }
+
+    #[test]
+    fn group_bitfield_maps_to_pins() {
+        let pins = [8, 3, 12];
+        assert_eq!(group_mask(&pins), 0x1108);
+        assert_eq!(group_spread(&pins, 0b101), 0x1100);
+        assert_eq!(group_gather(&pins, 0x1100 | 0x0001), 0b101);
+        for v in 0..8 {
+            assert_eq!(group_gather(&pins, group_spread(&pins, v)), v);
+        }
+    }
+}
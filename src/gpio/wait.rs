@@ -0,0 +1,154 @@
+//! `embedded_hal_async::digital::Wait` for input pins
+//!
+//! A waiting pin claims its EXTI line until the wait completes or the future
+//! is dropped. Pins with the same number on other ports share the line, they
+//! stay pending until it is released and then claim it in turn.
+//!
+//! Releasing the line restores the routing, edges and mask it had before the
+//! claim, so a handler registered with
+//! [`GpioPin::on_interrupt`](super::GpioPin::on_interrupt) keeps firing after
+//! the wait. While the wait is armed that handler sees the edges of the wait.
+
+use super::*;
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use critical_section::Mutex;
+
+struct Line {
+    /// Port whose pin currently owns the line
+    owner: Option<Port>,
+    /// Edge seen since the owner armed the line
+    fired: bool,
+    /// Line state before the owner claimed it
+    saved: Option<exti::LineState>,
+    /// One waker per port, i.e. per pin that can use the line
+    wakers: [Option<Waker>; 4],
+}
+
+const FREE: Line = Line {
+    owner: None,
+    fired: false,
+    saved: None,
+    wakers: [None, None, None, None],
+};
+static LINES: Mutex<RefCell<[Line; 16]>> = Mutex::new(RefCell::new([FREE; 16]));
+
+/// Wake the pin owning `line`, called by [`exti::dispatch`] with the pending
+/// flag cleared
+pub(crate) fn on_edge(line: u8) {
+    critical_section::with(|cs| {
+        let st = &mut LINES.borrow_ref_mut(cs)[line as usize];
+        if let Some(port) = st.owner {
+            // leave the line unmasked for a handler that was listening on it
+            if !st.saved.is_some_and(|saved| saved.listening()) {
+                exti::unlisten(line);
+            }
+            st.fired = true;
+            if let Some(waker) = st.wakers[port as usize].take() {
+                waker.wake();
+            }
+        }
+    })
+}
+
+/// Hands the line back when the wait completes or is cancelled
+struct Claim {
+    port: Port,
+    line: u8,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            let st = &mut LINES.borrow_ref_mut(cs)[self.line as usize];
+            st.wakers[self.port as usize] = None;
+            if st.owner != Some(self.port) {
+                return;
+            }
+
+            if let Some(saved) = st.saved.take() {
+                exti::restore(self.line, saved);
+            }
+            st.owner = None;
+            st.fired = false;
+            // let pins of the other ports claim the line
+            for waker in st.wakers.iter_mut().filter_map(Option::take) {
+                waker.wake();
+            }
+        })
+    }
+}
+
+impl<const P: char, const N: u8, PULL> GpioPin<P, N, Input<PULL>> {
+    /// Wait for `trigger`, or return at once if `done` already holds once the
+    /// line is armed
+    async fn wait_for(&mut self, trigger: IntTrigger, done: impl Fn(&Self) -> bool) {
+        let claim = Claim {
+            port: Self::port(),
+            line: N,
+        };
+
+        poll_fn(|cx| {
+            critical_section::with(|cs| {
+                let st = &mut LINES.borrow_ref_mut(cs)[N as usize];
+                match st.owner {
+                    None => {
+                        st.owner = Some(claim.port);
+                        st.fired = false;
+                        st.saved = Some(exti::save(N));
+                        exti::select_port(N, claim.port);
+                        exti::set_trigger(N, trigger);
+                        exti::clear_pending(N);
+                        exti::enable_vector(exti::vector(N));
+                        exti::listen(N);
+                        // the level may have changed before the line was armed
+                        if done(self) {
+                            return Poll::Ready(());
+                        }
+                    }
+                    Some(port) if port == claim.port && st.fired => return Poll::Ready(()),
+                    _ => {}
+                }
+                st.wakers[claim.port as usize] = Some(cx.waker().clone());
+                Poll::Pending
+            })
+        })
+        .await;
+
+        drop(claim);
+    }
+}
+
+impl<const P: char, const N: u8, PULL> embedded_hal_async::digital::Wait for GpioPin<P, N, Input<PULL>> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        if !self._is_low() {
+            return Ok(());
+        }
+        self.wait_for(IntTrigger::Rising, |pin| !pin._is_low()).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        if self._is_low() {
+            return Ok(());
+        }
+        self.wait_for(IntTrigger::Falling, |pin| pin._is_low()).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(IntTrigger::Rising, |_| false).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(IntTrigger::Falling, |_| false).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(IntTrigger::RisingFalling, |_| false).await;
+        Ok(())
+    }
+}
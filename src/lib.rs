@@ -1,10 +1,17 @@
 #![no_std]
 pub mod afio;
 pub mod clocks;
+pub mod exti;
 pub mod gpio;
+pub mod rcc;
+pub mod time;
+pub mod pwm;
 pub mod timer;
 pub mod delay;
 
 pub mod prelude {
+    pub use crate::gpio::Lockable as _;
+    pub use crate::rcc::{Enable as _, Reset as _};
+    pub use crate::time::{ExtU32 as _, RateExtU32 as _};
     pub use crate::timer::TimerBaseOp;
 }
@@ -0,0 +1,300 @@
+//! PWM output on the capture/compare channels of TIM1..TIM4
+//!
+//! ```ignore
+//! let pwm = Pwm::<TIM2, NoRemap>::new(dp.TIM2, &clocks, 1.kHz());
+//! let mut ch1 = pwm.ch1(gpioa.pa0.into_alternate_push_pull());
+//! ch1.set_duty(ch1.get_max_duty() / 2);
+//! ch1.enable();
+//! ```
+//!
+//! Channels only accept pins that carry them under the remap chosen for the
+//! timer, see [`crate::gpio::alt`].
+
+use crate::afio::Remap;
+use crate::clocks::{ClockListener, FrozenClocks};
+use crate::gpio::alt::{Ch1, Ch2, Ch3, Ch4};
+use crate::rcc::{Enable, Reset};
+use crate::time::Hertz;
+use ch32v1::ch32v103 as pac;
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use riscv::interrupt::free;
+
+/// Output compare PWM mode
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum PwmMode {
+    /// Active while the counter is below the duty
+    Mode1 = 0b110,
+    /// Inactive while the counter is below the duty
+    Mode2 = 0b111,
+}
+
+/// CHCTLRx value making channel `c` (0..=3) a preloaded PWM output
+const fn chctlr_value(reg: u16, c: u8, mode: PwmMode) -> u16 {
+    let offset = (c % 2) * 8;
+    // CCxS = 00 (output), OCxPE = 1, OCxM = mode
+    let field = (1 << 3) | ((mode as u16) << 4);
+    (reg & !(0xff << offset)) | (field << offset)
+}
+
+/// Timer running in PWM mode, `REMAP` selects the pins of its channels
+///
+/// As channels borrow the `Pwm`, it also implements [`ClockListener`] through
+/// a shared reference: pass `&mut &pwm` to [`Clocks::reconfigure`] while
+/// channels are in use.
+///
+/// [`Clocks::reconfigure`]: crate::clocks::Clocks::reconfigure
+pub struct Pwm<TIM, REMAP> {
+    tim: TIM,
+    clk: Cell<Hertz>,
+    freq: Cell<Hertz>,
+    _remap: PhantomData<REMAP>,
+}
+
+/// Channel `C` (0 for CH1 .. 3 for CH4) of a [`Pwm`] timer, owning its pin
+///
+/// The channel borrows the [`Pwm`], so the timer cannot be released while it
+/// still drives a pin.
+pub struct PwmChannel<'a, TIM, const C: u8, PIN> {
+    pin: PIN,
+    _tim: PhantomData<&'a TIM>,
+}
+
+macro_rules! pwm {
+    ($($TIM:ident: ($timclk:ident, $moe:expr),)+) => {
+        $(
+            impl<REMAP: Remap<pac::$TIM>> Pwm<pac::$TIM, REMAP> {
+                /// Enable the timer, apply `REMAP` and count at `freq`
+                pub fn new(tim: pac::$TIM, clocks: &FrozenClocks, freq: Hertz) -> Self {
+                    pac::$TIM::enable();
+                    pac::$TIM::reset();
+                    REMAP::apply();
+
+                    let pwm = Self {
+                        tim,
+                        clk: Cell::new(clocks.$timclk()),
+                        freq: Cell::new(freq),
+                        _remap: PhantomData,
+                    };
+                    pwm.set_period(freq);
+
+                    free(|| {
+                        pwm.tim.ctlr1.modify(|_, w| w.arpe().set_bit());
+                        if $moe {
+                            // advanced timers gate all outputs with MOE
+                            pwm.tim.bdtr.modify(|_, w| w.moe().set_bit());
+                        }
+                        pwm.tim.ctlr1.modify(|_, w| w.cen().set_bit());
+                    });
+                    pwm
+                }
+
+                /// Change the PWM frequency, duties keep their raw value
+                ///
+                /// ATRLR is capped at 0xfffe so that [`get_max_duty`](Self::get_max_duty)
+                /// still fits a `u16` and 100% duty stays reachable.
+                pub fn set_period(&self, freq: Hertz) {
+                    self.freq.set(freq);
+                    let config = crate::timer::TimBaseConfig::from_rate(self.clk.get(), freq);
+                    let period = config.period.min(u16::MAX - 1);
+
+                    free(|| {
+                        self.tim.psc.write(|w| unsafe { w.bits(config.prescaler) });
+                        self.tim.atrlr.write(|w| unsafe { w.bits(period) });
+                        // load PSC/ATRLR and the preloaded duties, URS keeps UIF clear
+                        self.tim.ctlr1.modify(|_, w| w.urs().set_bit());
+                        self.tim.swevgr.write(|w| w.ug().set_bit());
+                        self.tim.ctlr1.modify(|_, w| w.urs().clear_bit());
+                    });
+                }
+
+                /// Duty that keeps the output active for the whole period
+                pub fn get_max_duty(&self) -> u16 {
+                    self.tim.atrlr.read().bits().saturating_add(1)
+                }
+
+                fn channel<const C: u8, PIN>(&self, pin: PIN) -> PwmChannel<'_, pac::$TIM, C, PIN> {
+                    let mut ch: PwmChannel<'_, pac::$TIM, C, PIN> = PwmChannel {
+                        pin,
+                        _tim: PhantomData,
+                    };
+                    ch.set_mode(PwmMode::Mode1);
+                    ch
+                }
+
+                pub fn ch1<PIN: Ch1<pac::$TIM, REMAP>>(&self, pin: PIN) -> PwmChannel<'_, pac::$TIM, 0, PIN> {
+                    self.channel(pin)
+                }
+
+                pub fn ch2<PIN: Ch2<pac::$TIM, REMAP>>(&self, pin: PIN) -> PwmChannel<'_, pac::$TIM, 1, PIN> {
+                    self.channel(pin)
+                }
+
+                pub fn ch3<PIN: Ch3<pac::$TIM, REMAP>>(&self, pin: PIN) -> PwmChannel<'_, pac::$TIM, 2, PIN> {
+                    self.channel(pin)
+                }
+
+                pub fn ch4<PIN: Ch4<pac::$TIM, REMAP>>(&self, pin: PIN) -> PwmChannel<'_, pac::$TIM, 3, PIN> {
+                    self.channel(pin)
+                }
+
+                /// Keep the frequency for a new timer clock, duties are rescaled to
+                /// the new period
+                fn set_clock(&self, clk: Hertz) {
+                    let old_max = self.get_max_duty() as u32;
+                    self.clk.set(clk);
+                    self.set_period(self.freq.get());
+
+                    let new_max = self.get_max_duty() as u32;
+                    let scale = |duty: u16| (duty as u32 * new_max / old_max) as u16;
+                    self.tim.ch1cvr.modify(|r, w| unsafe { w.bits(scale(r.bits())) });
+                    self.tim.ch2cvr.modify(|r, w| unsafe { w.bits(scale(r.bits())) });
+                    self.tim.ch3cvr.modify(|r, w| unsafe { w.bits(scale(r.bits())) });
+                    self.tim.ch4cvr.modify(|r, w| unsafe { w.bits(scale(r.bits())) });
+                }
+
+                /// Stop the counter and give back the timer, once all channels are
+                /// released
+                pub fn release(self) -> pac::$TIM {
+                    free(|| self.tim.ctlr1.modify(|_, w| w.cen().clear_bit()));
+                    self.tim
+                }
+            }
+
+            impl<REMAP: Remap<pac::$TIM>> ClockListener for Pwm<pac::$TIM, REMAP> {
+                fn clocks_changed(&mut self, clocks: &FrozenClocks) {
+                    self.set_clock(clocks.$timclk());
+                }
+            }
+
+            impl<REMAP: Remap<pac::$TIM>> ClockListener for &Pwm<pac::$TIM, REMAP> {
+                fn clocks_changed(&mut self, clocks: &FrozenClocks) {
+                    self.set_clock(clocks.$timclk());
+                }
+            }
+
+            impl<const C: u8, PIN> PwmChannel<'_, pac::$TIM, C, PIN> {
+                #[inline(always)]
+                fn regs(&self) -> &'static <pac::$TIM as core::ops::Deref>::Target {
+                    unsafe { &(*pac::$TIM::ptr()) }
+                }
+
+                /// Select PWM mode 1 or 2, the compare value stays preloaded
+                pub fn set_mode(&mut self, mode: PwmMode) {
+                    let tim = self.regs();
+                    free(|| {
+                        if C < 2 {
+                            tim.chctlr1o().modify(|r, w| unsafe { w.bits(chctlr_value(r.bits(), C, mode)) })
+                        } else {
+                            tim.chctlr2o().modify(|r, w| unsafe { w.bits(chctlr_value(r.bits(), C, mode)) })
+                        }
+                    })
+                }
+
+                /// Drive the pin from the compare output
+                pub fn enable(&mut self) {
+                    let tim = self.regs();
+                    free(|| tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() | (1 << (4 * C))) }))
+                }
+
+                pub fn disable(&mut self) {
+                    let tim = self.regs();
+                    free(|| tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (4 * C))) }))
+                }
+
+                /// ATRLR + 1, see [`Pwm::set_period`]
+                pub fn get_max_duty(&self) -> u16 {
+                    self.regs().atrlr.read().bits().saturating_add(1)
+                }
+
+                pub fn get_duty(&self) -> u16 {
+                    let tim = self.regs();
+                    match C {
+                        0 => tim.ch1cvr.read().bits(),
+                        1 => tim.ch2cvr.read().bits(),
+                        2 => tim.ch3cvr.read().bits(),
+                        _ => tim.ch4cvr.read().bits(),
+                    }
+                }
+
+                /// Set the compare value, taking effect at the next period
+                pub fn set_duty(&mut self, duty: u16) {
+                    let tim = self.regs();
+                    match C {
+                        0 => tim.ch1cvr.write(|w| unsafe { w.bits(duty) }),
+                        1 => tim.ch2cvr.write(|w| unsafe { w.bits(duty) }),
+                        2 => tim.ch3cvr.write(|w| unsafe { w.bits(duty) }),
+                        _ => tim.ch4cvr.write(|w| unsafe { w.bits(duty) }),
+                    }
+                }
+
+                /// Disable the output and give back the pin
+                pub fn release(mut self) -> PIN {
+                    self.disable();
+                    self.pin
+                }
+            }
+
+            impl<const C: u8, PIN> embedded_hal::pwm::ErrorType for PwmChannel<'_, pac::$TIM, C, PIN> {
+                type Error = Infallible;
+            }
+
+            impl<const C: u8, PIN> embedded_hal::pwm::SetDutyCycle for PwmChannel<'_, pac::$TIM, C, PIN> {
+                fn max_duty_cycle(&self) -> u16 {
+                    self.get_max_duty()
+                }
+
+                fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+                    self.set_duty(duty);
+                    Ok(())
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-02")]
+            impl<const C: u8, PIN> embedded_hal_02::PwmPin for PwmChannel<'_, pac::$TIM, C, PIN> {
+                type Duty = u16;
+
+                fn disable(&mut self) {
+                    Self::disable(self)
+                }
+
+                fn enable(&mut self) {
+                    Self::enable(self)
+                }
+
+                fn get_duty(&self) -> u16 {
+                    Self::get_duty(self)
+                }
+
+                fn get_max_duty(&self) -> u16 {
+                    Self::get_max_duty(self)
+                }
+
+                fn set_duty(&mut self, duty: u16) {
+                    Self::set_duty(self, duty)
+                }
+            }
+        )+
+    };
+}
+
+pwm! {
+    TIM1: (pclk2_tim, true),
+    TIM2: (pclk1_tim, false),
+    TIM3: (pclk1_tim, false),
+    TIM4: (pclk1_tim, false),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chctlr_value_touches_only_channel_byte() {
+        assert_eq!(chctlr_value(0x0000, 0, PwmMode::Mode1), 0x0068);
+        assert_eq!(chctlr_value(0x0000, 1, PwmMode::Mode2), 0x7800);
+        assert_eq!(chctlr_value(0xffff, 0, PwmMode::Mode1), 0xff68);
+        assert_eq!(chctlr_value(0x0068, 3, PwmMode::Mode1), 0x6868);
+    }
+}
@@ -0,0 +1,181 @@
+use ch32v1::ch32v103::{self as pac, RCC};
+use core::cell::Cell;
+use critical_section::Mutex;
+use riscv::interrupt::free;
+
+/// Reason of the last reset, decoded from RSTSCKR
+///
+/// When several flags are set the most specific one wins, e.g. a power-on
+/// reset also sets the pin reset flag.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetCause {
+    /// Low-power management reset (entering standby/stop while disallowed)
+    LowPower,
+    /// Window watchdog reset
+    WindowWatchdog,
+    /// Independent watchdog reset
+    IndependentWatchdog,
+    /// Software reset through PFIC
+    Software,
+    /// Power-on or power-down reset
+    PowerOn,
+    /// NRST pin reset
+    Pin,
+    /// No flag set, e.g. flags cleared by a previous boot stage
+    Unknown,
+}
+
+static RESET_CAUSE: Mutex<Cell<Option<ResetCause>>> = Mutex::new(Cell::new(None));
+
+/// Read the cause of the last reset and clear the flags through RMVF
+///
+/// The first call latches the value, later calls return the same cause, so it
+/// can be read before any peripheral init and logged afterwards.
+pub fn reset_cause() -> ResetCause {
+    critical_section::with(|cs| {
+        let cell = RESET_CAUSE.borrow(cs);
+        if let Some(cause) = cell.get() {
+            return cause;
+        }
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let r = rcc.rstsckr.read();
+
+        let cause = if r.lpwrrstf().bit() {
+            ResetCause::LowPower
+        } else if r.wwdgrstf().bit() {
+            ResetCause::WindowWatchdog
+        } else if r.iwdgrstf().bit() {
+            ResetCause::IndependentWatchdog
+        } else if r.sftrstf().bit() {
+            ResetCause::Software
+        } else if r.porrstf().bit() {
+            ResetCause::PowerOn
+        } else if r.pinrstf().bit() {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        };
+
+        rcc.rstsckr.modify(|_, w| w.rmvf().clear());
+
+        cell.set(Some(cause));
+        cause
+    })
+}
+
+/// Peripheral whose bus clock is gated by the RCC
+pub trait Enable {
+    /// Turn on the bus clock of the peripheral
+    fn enable();
+    /// Turn off the bus clock of the peripheral
+    fn disable();
+    fn is_enabled() -> bool;
+}
+
+/// Peripheral that can be reset through the RCC
+pub trait Reset {
+    /// Pulse the reset line of the peripheral, returning its registers to defaults
+    fn reset();
+}
+
+macro_rules! enable {
+    ($($PER:ident => ($enr:ident, $en:ident),)+) => {
+        $(
+            impl Enable for pac::$PER {
+                #[inline]
+                fn enable() {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    free(|| rcc.$enr.modify(|_, w| w.$en().set_bit()));
+                }
+
+                #[inline]
+                fn disable() {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    free(|| rcc.$enr.modify(|_, w| w.$en().clear_bit()));
+                }
+
+                #[inline]
+                fn is_enabled() -> bool {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    rcc.$enr.read().$en().bit_is_set()
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! reset {
+    ($($PER:ident => ($rstr:ident, $rst:ident),)+) => {
+        $(
+            impl Reset for pac::$PER {
+                #[inline]
+                fn reset() {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    free(|| {
+                        rcc.$rstr.modify(|_, w| w.$rst().set_bit());
+                        rcc.$rstr.modify(|_, w| w.$rst().clear_bit());
+                    });
+                }
+            }
+        )+
+    };
+}
+
+enable! {
+    DMA => (ahbpcenr, dmaen),
+    CRC => (ahbpcenr, crcen),
+    USBHD => (ahbpcenr, usbhden),
+
+    AFIO => (apb2pcenr, afioen),
+    GPIOA => (apb2pcenr, iopaen),
+    GPIOB => (apb2pcenr, iopben),
+    GPIOC => (apb2pcenr, iopcen),
+    GPIOD => (apb2pcenr, iopden),
+    ADC => (apb2pcenr, adcen),
+    TIM1 => (apb2pcenr, tim1en),
+    SPI1 => (apb2pcenr, spi1en),
+    USART1 => (apb2pcenr, usart1en),
+
+    TIM2 => (apb1pcenr, tim2en),
+    TIM3 => (apb1pcenr, tim3en),
+    TIM4 => (apb1pcenr, tim4en),
+    WWDG => (apb1pcenr, wwdgen),
+    SPI2 => (apb1pcenr, spi2en),
+    USART2 => (apb1pcenr, usart2en),
+    USART3 => (apb1pcenr, usart3en),
+    I2C1 => (apb1pcenr, i2c1en),
+    I2C2 => (apb1pcenr, i2c2en),
+    USBD => (apb1pcenr, usbden),
+    BKP => (apb1pcenr, bkpen),
+    PWR => (apb1pcenr, pwren),
+    DAC1 => (apb1pcenr, dacen),
+}
+
+reset! {
+    USBHD => (ahbrstr, usbhdrst),
+
+    AFIO => (apb2prstr, afiorst),
+    GPIOA => (apb2prstr, ioparst),
+    GPIOB => (apb2prstr, iopbrst),
+    GPIOC => (apb2prstr, iopcrst),
+    GPIOD => (apb2prstr, iopdrst),
+    ADC => (apb2prstr, adcrst),
+    TIM1 => (apb2prstr, tim1rst),
+    SPI1 => (apb2prstr, spi1rst),
+    USART1 => (apb2prstr, usart1rst),
+
+    TIM2 => (apb1prstr, tim2rst),
+    TIM3 => (apb1prstr, tim3rst),
+    TIM4 => (apb1prstr, tim4rst),
+    WWDG => (apb1prstr, wwdgrst),
+    SPI2 => (apb1prstr, spi2rst),
+    USART2 => (apb1prstr, usart2rst),
+    USART3 => (apb1prstr, usart3rst),
+    I2C1 => (apb1prstr, i2c1rst),
+    I2C2 => (apb1prstr, i2c2rst),
+    USBD => (apb1prstr, usbdrst),
+    BKP => (apb1prstr, bkprst),
+    PWR => (apb1prstr, pwrrst),
+    DAC1 => (apb1prstr, dacrst),
+}
@@ -0,0 +1,9 @@
+//! Typed frequencies and durations
+//!
+//! Re-exports of the [`fugit`] types used throughout the HAL, so that mixing
+//! MHz and Hz or microseconds and milliseconds is a compile error.
+
+pub use fugit::{
+    ExtU32, HertzU32 as Hertz, KilohertzU32 as KiloHertz, MegahertzU32 as MegaHertz,
+    MicrosDurationU32, MillisDurationU32, RateExtU32,
+};
@@ -1,4 +1,8 @@
+use crate::clocks::{ClockListener, FrozenClocks};
+use crate::rcc::{Enable, Reset};
+use crate::time::{Hertz, MicrosDurationU32};
 use ch32v1::ch32v103 as pac;
+use core::convert::Infallible;
 use riscv::interrupt::free;
 
 pub trait TimerBaseOp<Tim> {
@@ -10,9 +14,12 @@ pub trait TimerBaseOp<Tim> {
     fn disable(&self) -> Self::Result;
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum TimerError {
     EnableFailed,
     DisableFailed,
+    /// `cancel` on a timer that is not counting
+    NotRunning,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -27,13 +34,27 @@ pub enum Tim {
 pub enum CounterMode {
     Up = 0,
     Down = 1,
+    /// Up and down, compare flags set while counting down
+    CenterAligned1,
+    /// Up and down, compare flags set while counting up
+    CenterAligned2,
+    /// Up and down, compare flags set both ways
+    CenterAligned3,
 }
 
 impl CounterMode {
+    /// DIR bit, set for down-counting
     pub fn val(&self) -> bool {
+        matches!(self, Self::Down)
+    }
+
+    /// CMS field, 0 for edge-aligned modes
+    pub fn cms(&self) -> u8 {
         match self {
-            Self::Up => true,
-            Self::Down => false
+            Self::Up | Self::Down => 0b00,
+            Self::CenterAligned1 => 0b01,
+            Self::CenterAligned2 => 0b10,
+            Self::CenterAligned3 => 0b11,
         }
     }
 }
@@ -45,9 +66,12 @@ pub enum ClockDivision {
     Div3 = 2,
 }
 
+/// When new PSC/ATRLR values take effect
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum PSCReloadMode {
+    /// At the next update event
     Update,
+    /// Right away, through a software update event
     Immediate
 }
 
@@ -58,7 +82,37 @@ pub struct TimBaseConfig {
     pub period: u16,
     pub clock_division: ClockDivision,
     pub repetition_counter: u16,
-    pub psc_reload_mode: PSCReloadMode
+    pub psc_reload_mode: PSCReloadMode,
+    /// Buffer ATRLR writes until the next update event (ARPE)
+    pub auto_reload_preload: bool,
+}
+
+/// Prescaler and auto-reload value for an update every `ticks` timer clocks,
+/// saturating at the longest period the 16-bit registers allow
+fn psc_arr(ticks: u32) -> (u16, u16) {
+    let ticks = ticks.max(1);
+    let prescaler = (ticks - 1) / (1 << 16);
+    let period = (ticks / (prescaler + 1)).clamp(1, 1 << 16) - 1;
+
+    (prescaler.min(u16::MAX as u32) as u16, period as u16)
+}
+
+impl TimBaseConfig {
+    /// Up-counting configuration with an update event at `rate`, for a timer
+    /// clocked at `timclk`
+    pub fn from_rate(timclk: Hertz, rate: Hertz) -> Self {
+        let (prescaler, period) = psc_arr(timclk.raw() / rate.raw().max(1));
+
+        Self {
+            prescaler,
+            counter_mode: CounterMode::Up,
+            period,
+            clock_division: ClockDivision::Div1,
+            repetition_counter: 0,
+            psc_reload_mode: PSCReloadMode::Update,
+            auto_reload_preload: false,
+        }
+    }
 }
 
 pub enum ADVTimer {
@@ -70,83 +124,242 @@ pub struct AdvancedTimer {
     pub config: TimBaseConfig,
 }
 
-impl TimerBaseOp<ADVTimer> for AdvancedTimer {
-    type Result = nb::Result<(), TimerError>;
-
-    fn new(tim: ADVTimer, config: TimBaseConfig) -> Self {
-        let reg = match tim {
-            ADVTimer::TIM1 => unsafe { &(*(pac::TIM1::ptr())) }
-        };
-        free(|| {
-            unsafe {
-                reg.ctlr1.modify(|_, w| {
-                    // set timer count mode
-                    w.dir().bit(config.counter_mode.val())
-                    // set clock division factor
-                     .ckd().bits(config.clock_division as u8)
+pub enum GPTimer {
+    TIM2,
+    TIM3,
+    TIM4,
+}
+
+/// Time base of the general-purpose timers TIM2..TIM4
+///
+/// Same as [`AdvancedTimer`] without the repetition counter, which these
+/// timers lack. The timer clock for `config` is `pclk1_tim`.
+pub struct GeneralTimer {
+    pub tim: GPTimer,
+    pub config: TimBaseConfig,
+}
+
+/// `TimerBaseOp` for a timer selector, `rptcr` is given for timers with a
+/// repetition counter
+macro_rules! timer_base_op {
+    ($Timer:ident: $Sel:ident, $Regs:ty, [$($TIM:ident),+] $(, $rptcr:ident)?) => {
+        impl $Sel {
+            #[inline(always)]
+            fn regs(&self) -> &'static $Regs {
+                match self {
+                    $($Sel::$TIM => unsafe { &(*pac::$TIM::ptr()) },)+
+                }
+            }
+        }
+
+        impl TimerBaseOp<$Sel> for $Timer {
+            type Result = nb::Result<(), TimerError>;
+
+            fn new(tim: $Sel, config: TimBaseConfig) -> Self {
+                match tim {
+                    $($Sel::$TIM => {
+                        pac::$TIM::enable();
+                        pac::$TIM::reset();
+                    })+
+                }
+                let reg = tim.regs();
+
+                free(|| {
+                    unsafe {
+                        reg.ctlr1.modify(|_, w| {
+                            // set timer count mode, DIR is read-only in center-aligned modes
+                            w.dir().bit(config.counter_mode.val())
+                             .cms().bits(config.counter_mode.cms())
+                            // set clock division factor
+                             .ckd().bits(config.clock_division as u8)
+                            // buffer auto reload value
+                             .arpe().bit(config.auto_reload_preload)
+                        });
+                        //set timer auto reload value
+                        reg.atrlr.write(|w| w.bits(config.period));
+                        // set timer prescaler
+                        reg.psc.write(|w| w.bits(config.prescaler));
+                        $(
+                            // set timer reptition counter
+                            reg.$rptcr.write(|w| w.bits(config.repetition_counter));
+                        )?
+                    }
+
+                    if config.psc_reload_mode == PSCReloadMode::Immediate {
+                        // load the buffered registers now, then drop the UIF raised by UG
+                        reg.swevgr.write(|w| w.ug().set_bit());
+                        reg.intfr.write(|w| unsafe { w.bits(!1) });
+                    }
                 });
-                //set timer auto reload value
-                reg.atrlr.modify(|_, w| w.bits(config.period));
-                // set timer prescaler
-                reg.psc.modify(|_, w| w.bits(config.prescaler));
-                // set timer reptition counter
-                reg.rptcr.modify(|_, w| w.bits(config.repetition_counter));
-                // set prescaler reload mode
-                reg.swevgr.write(|w| w.bits(config.psc_reload_mode as u16))
+
+                Self { tim, config }
             }
-        });
-        
 
-        Self {
-            tim: tim,
-            config: config,
+            #[inline]
+            fn enable(&self) -> Self::Result {
+                let reg = self.tim.regs();
+
+                free(|| {
+                    reg.ctlr1.modify(|r, w| {
+                        if r.cen().is_disabled() {
+                            w.cen().enabled()
+                        } else {
+                            w
+                        }
+                    });
+
+                    if reg.ctlr1.read().cen().is_enabled() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::Other(TimerError::EnableFailed))
+                    }
+                })
+            }
+
+            #[inline]
+            fn disable(&self) -> Self::Result {
+                let reg = self.tim.regs();
+
+                free(|| {
+                    reg.ctlr1.modify(|r, w| {
+                        if r.cen().is_enabled() {
+                            w.cen().disabled()
+                        } else {
+                            w
+                        }
+                    });
+
+                    if reg.ctlr1.read().cen().is_disabled() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::Other(TimerError::DisableFailed))
+                    }
+                })
+            }
         }
+    };
+}
 
-    }
+timer_base_op!(AdvancedTimer: ADVTimer, pac::tim1::RegisterBlock, [TIM1], rptcr);
+timer_base_op!(GeneralTimer: GPTimer, pac::tim2::RegisterBlock, [TIM2, TIM3, TIM4]);
+
+/// Count-down timer on one of TIM1..TIM4
+///
+/// Unlike [`AdvancedTimer`] and [`GeneralTimer`], which only set up the time
+/// base, this owns the peripheral and derives the period from the clocks. Use
+/// one or the other on a given timer, both reset it when created.
+///
+/// The timer clock is taken from the frozen clocks, i.e. it already includes
+/// the x2 multiplier applied when the APB prescaler divides.
+pub struct Timer<TIM> {
+    tim: TIM,
+    clk: Hertz,
+    /// Period of the last `start`, re-derived when the clocks change
+    timeout: Option<MicrosDurationU32>,
+}
+
+macro_rules! timers {
+    ($($TIM:ident: $timclk:ident,)+) => {
+        $(
+            impl Timer<pac::$TIM> {
+                /// Enable and reset the timer through the RCC
+                pub fn new(tim: pac::$TIM, clocks: &FrozenClocks) -> Self {
+                    pac::$TIM::enable();
+                    pac::$TIM::reset();
 
-    #[inline]
-    fn enable(&self) -> Self::Result {
-        let reg = match self.tim {
-            ADVTimer::TIM1 => unsafe { &(*pac::TIM1::ptr()) },
-        };
-
-        free(|| {
-            reg.ctlr1.modify(|r, w| {
-                if r.cen().is_disabled() {
-                    w.cen().enabled()
-                } else {
-                    w
+                    Self {
+                        tim,
+                        clk: clocks.$timclk(),
+                        timeout: None,
+                    }
                 }
-            });
 
-            if reg.ctlr1.read().cen().is_enabled() {
-                Ok(())
-            } else {
-                Err(nb::Error::Other(TimerError::EnableFailed))
-            }
-        })
-    }
+                /// Clock the counter runs from, before the prescaler
+                pub fn clock(&self) -> Hertz {
+                    self.clk
+                }
+
+                /// (Re)start the timer, the update flag is set every `timeout`
+                pub fn start(&mut self, timeout: MicrosDurationU32) {
+                    self.timeout = Some(timeout);
+                    let ticks = self.clk.raw() as u64 * timeout.to_micros() as u64 / 1_000_000;
+                    let (psc, arr) = psc_arr(ticks.min(u32::MAX as u64) as u32);
+
+                    free(|| {
+                        self.tim.ctlr1.modify(|_, w| w.cen().clear_bit());
+                        self.tim.psc.write(|w| unsafe { w.bits(psc) });
+                        self.tim.atrlr.write(|w| unsafe { w.bits(arr) });
+                        // load PSC and ATRLR now, URS keeps UG from setting UIF
+                        self.tim.ctlr1.modify(|_, w| w.urs().set_bit());
+                        self.tim.swevgr.write(|w| w.ug().set_bit());
+                        self.tim.intfr.write(|w| unsafe { w.bits(!1) });
+                        self.tim.ctlr1.modify(|_, w| w.urs().clear_bit().cen().set_bit());
+                    });
+                }
+
+                /// Complete once per period, clearing the update flag
+                pub fn wait(&mut self) -> nb::Result<(), Infallible> {
+                    if self.is_update_pending() {
+                        self.clear_update_flag();
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
 
-    #[inline]
-    fn disable(&self) -> Self::Result {
-        let reg = match self.tim {
-            ADVTimer::TIM1 => unsafe { &(*pac::TIM1::ptr()) },
-        };
-
-        free(|| {
-            reg.ctlr1.modify(|r, w| {
-                if r.cen().is_enabled() {
-                    w.cen().disabled()
-                } else {
-                    w
+                /// Stop a started timer
+                pub fn cancel(&mut self) -> Result<(), TimerError> {
+                    if self.tim.ctlr1.read().cen().bit_is_clear() {
+                        return Err(TimerError::NotRunning);
+                    }
+                    free(|| self.tim.ctlr1.modify(|_, w| w.cen().clear_bit()));
+                    self.clear_update_flag();
+                    Ok(())
                 }
-            });
 
-            if reg.ctlr1.read().cen().is_disabled() {
-                Ok(())
-            } else {
-                Err(nb::Error::Other(TimerError::DisableFailed))
+                /// Raise the update interrupt on every period
+                pub fn listen(&mut self) {
+                    free(|| self.tim.dmaintenr.modify(|_, w| w.uie().set_bit()));
+                }
+
+                pub fn unlisten(&mut self) {
+                    free(|| self.tim.dmaintenr.modify(|_, w| w.uie().clear_bit()));
+                }
+
+                pub fn is_update_pending(&self) -> bool {
+                    self.tim.intfr.read().uif().bit_is_set()
+                }
+
+                /// Clear UIF only, INTFR bits are cleared by writing 0
+                pub fn clear_update_flag(&mut self) {
+                    self.tim.intfr.write(|w| unsafe { w.bits(!1) });
+                }
+
+                /// Stop the timer and give back the peripheral
+                pub fn release(mut self) -> pac::$TIM {
+                    let _ = self.cancel();
+                    self.tim
+                }
             }
-        })
-    }
+
+            impl ClockListener for Timer<pac::$TIM> {
+                /// Restart a running timer with dividers for the new clock
+                fn clocks_changed(&mut self, clocks: &FrozenClocks) {
+                    self.clk = clocks.$timclk();
+                    if let Some(timeout) = self.timeout {
+                        if self.tim.ctlr1.read().cen().bit_is_set() {
+                            self.start(timeout);
+                        }
+                    }
+                }
+            }
+        )+
+    };
+}
+
+timers! {
+    TIM1: pclk2_tim,
+    TIM2: pclk1_tim,
+    TIM3: pclk1_tim,
+    TIM4: pclk1_tim,
 }